@@ -0,0 +1,147 @@
+use chrono::{SecondsFormat, Utc};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket, UnixDatagram},
+};
+
+/// RFC 5424 priority of alerts: facility security/authorization (4) and
+/// severity alert (1).
+const PRIORITY: u8 = 4 * 8 + 1;
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+/// Bounds the delay a slow or unreachable receiver adds to a response.
+const SEND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Destination of the detection alerts, parsed from `udp://host:port`,
+/// `tcp://host:port` or `unix:///path/to/socket`.
+pub enum AlertSink {
+    Udp(String),
+    Tcp(String),
+    Unix(String),
+}
+
+pub struct Detection<'a> {
+    pub signature: &'a str,
+    pub sha256: Option<&'a str>,
+    pub file_name: Option<&'a str>,
+    pub size: u64,
+    pub client_address: Option<&'a str>,
+}
+
+impl AlertSink {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        match url.split_once("://") {
+            Some(("udp", addr)) => Ok(Self::Udp(addr.to_owned())),
+            Some(("tcp", addr)) => Ok(Self::Tcp(addr.to_owned())),
+            Some(("unix", path)) => Ok(Self::Unix(path.to_owned())),
+            _ => Err(format!("unsupported syslog URL '{}'", url)),
+        }
+    }
+
+    /// Sends the detection as RFC 5424 message with a CEF payload. TCP uses
+    /// octet counting framing as per RFC 6587. Gives up after
+    /// [`SEND_TIMEOUT`].
+    pub async fn send(&self, detection: &Detection<'_>) -> Result<(), std::io::Error> {
+        let msg = syslog_message(&cef(detection));
+        tokio::time::timeout(SEND_TIMEOUT, self.deliver(&msg))
+            .await
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("no response within {:?}", SEND_TIMEOUT),
+                )
+            })?
+    }
+
+    async fn deliver(&self, msg: &str) -> Result<(), std::io::Error> {
+        match self {
+            Self::Udp(addr) => {
+                let bind: SocketAddr = match addr.parse::<SocketAddr>() {
+                    Ok(SocketAddr::V6(_)) => "[::]:0".parse().unwrap(),
+                    _ => "0.0.0.0:0".parse().unwrap(),
+                };
+                let socket = UdpSocket::bind(bind).await?;
+                socket.send_to(msg.as_bytes(), addr).await?;
+            }
+            Self::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr).await?;
+                stream
+                    .write_all(format!("{} {}", msg.len(), msg).as_bytes())
+                    .await?;
+                stream.shutdown().await?;
+            }
+            Self::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.send_to(msg.as_bytes(), path).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn syslog_message(cef: &str) -> String {
+    format!(
+        "<{}>1 {} {} {} {} detection - {}",
+        PRIORITY,
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        hostname().unwrap_or_else(|| "-".to_owned()),
+        APP_NAME,
+        std::process::id(),
+        cef,
+    )
+}
+
+fn cef(d: &Detection<'_>) -> String {
+    let mut cef = format!(
+        "CEF:0|rntrp|{}|{}|{}|Malware detected|10|",
+        APP_NAME,
+        env!("CARGO_PKG_VERSION"),
+        escape_header(d.signature),
+    );
+    let mut ext = vec![
+        ("act", "detected".to_owned()),
+        ("rt", Utc::now().timestamp_millis().to_string()),
+        ("cs1Label", "signature".to_owned()),
+        ("cs1", d.signature.to_owned()),
+        ("fsize", d.size.to_string()),
+    ];
+    if let Some(sha256) = d.sha256 {
+        ext.push(("fileHash", sha256.to_owned()));
+    }
+    if let Some(file_name) = d.file_name {
+        ext.push(("fname", file_name.to_owned()));
+    }
+    if let Some(addr) = d.client_address {
+        ext.push(("src", addr.to_owned()));
+    }
+    let ext = ext
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, escape_extension(v)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    cef.push_str(&ext);
+    cef
+}
+
+fn escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    match unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } {
+        0 => {
+            let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+        }
+        _ => None,
+    }
+}
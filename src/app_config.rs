@@ -1,22 +1,86 @@
-use std::fmt;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
 
-use config::Config;
-use serde::{Deserialize, Serialize};
+use clap::ValueEnum;
+use config::{Config, Source as _};
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, Unexpected, Visitor},
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    alert::AlertSink, fetch::FetchConfig, hashes::HashAlgorithm, policy::Policy, s3::S3Config,
+    tus::TusConfig, watch::WatchConfig,
+};
+
+/// Looked up in the working directory unless a path is given explicitly.
+const DEFAULT_FILES: &[&str] = &["Config.toml", "Config.yaml", "Config.yml"];
+const ENV_PREFIX: &str = "APP";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
 
 #[derive(Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct AppConfig {
     pub enable_shutdown_endpoint: bool,
+    pub enable_admin_endpoint: bool,
+    #[serde(deserialize_with = "deserialize_size")]
     pub max_file_size: usize,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_request_size: usize,
+    pub max_files_per_request: usize,
     pub port: u16,
+    pub max_db_age_secs: Option<u64>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub min_tmp_free_bytes: u64,
+    pub hashes: Vec<HashAlgorithm>,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
+    pub log_format: LogFormat,
+    pub log_level: Option<String>,
+    pub audit_log: Option<String>,
+    pub alert_syslog: Option<String>,
+    pub proxy_upstream: Option<String>,
+    pub s3: Option<S3Config>,
+    pub fetch: Option<FetchConfig>,
+    pub tus: Option<TusConfig>,
+    pub policies: HashMap<String, Policy>,
+    pub watch: WatchConfig,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             enable_shutdown_endpoint: false,
+            enable_admin_endpoint: false,
             max_file_size: usize::MAX,
+            max_request_size: usize::MAX,
+            max_files_per_request: usize::MAX,
             port: 8000,
+            max_db_age_secs: None,
+            min_tmp_free_bytes: 0,
+            hashes: HashAlgorithm::DEFAULT.to_vec(),
+            otlp_endpoint: None,
+            otlp_service_name: env!("CARGO_PKG_NAME").to_owned(),
+            log_format: LogFormat::Text,
+            log_level: None,
+            audit_log: None,
+            alert_syslog: None,
+            proxy_upstream: None,
+            s3: None,
+            fetch: None,
+            tus: None,
+            policies: HashMap::new(),
+            watch: WatchConfig::default(),
         }
     }
 }
@@ -27,20 +91,342 @@ impl fmt::Display for AppConfig {
             f,
             concat!(
                 "\tenable_shutdown_endpoint: {}\n",
+                "\tenable_admin_endpoint: {}\n",
                 "\tmax_file_size: {}\n",
-                "\tport: {}",
+                "\tmax_request_size: {}\n",
+                "\tmax_files_per_request: {}\n",
+                "\tport: {}\n",
+                "\tmax_db_age_secs: {:?}\n",
+                "\tmin_tmp_free_bytes: {}\n",
+                "\thashes: {:?}\n",
+                "\totlp_endpoint: {:?}\n",
+                "\totlp_service_name: {}\n",
+                "\tlog_format: {:?}\n",
+                "\tlog_level: {:?}\n",
+                "\taudit_log: {:?}\n",
+                "\talert_syslog: {:?}\n",
+                "\tproxy_upstream: {:?}\n",
+                "\ts3: {:?}\n",
+                "\tfetch: {:?}\n",
+                "\ttus: {:?}\n",
+                "\tpolicies: {:?}\n",
+                "\twatch: {:?}",
             ),
-            self.enable_shutdown_endpoint, self.max_file_size, self.port,
+            self.enable_shutdown_endpoint,
+            self.enable_admin_endpoint,
+            self.max_file_size,
+            self.max_request_size,
+            self.max_files_per_request,
+            self.port,
+            self.max_db_age_secs,
+            self.min_tmp_free_bytes,
+            self.hashes,
+            self.otlp_endpoint,
+            self.otlp_service_name,
+            self.log_format,
+            self.log_level,
+            self.audit_log,
+            self.alert_syslog,
+            self.proxy_upstream,
+            self.s3.as_ref().map(|s3| &s3.endpoint),
+            self.fetch.as_ref().map(|fetch| &fetch.hosts),
+            self.tus,
+            self.policies.keys().collect::<Vec<_>>(),
+            self.watch.dirs,
         )
     }
 }
 
-pub fn load() -> AppConfig {
-    Config::builder()
-        .add_source(config::File::with_name("Config"))
-        .add_source(config::Environment::with_prefix("app"))
-        .build()
-        .unwrap_or_default()
-        .try_deserialize()
-        .unwrap_or_default()
+impl AppConfig {
+    /// Checks constraints which cannot be expressed by the types alone.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let limits = [
+            ("max_file_size", self.max_file_size),
+            ("max_request_size", self.max_request_size),
+            ("max_files_per_request", self.max_files_per_request),
+        ];
+        for (key, limit) in limits {
+            if limit == 0 {
+                problems.push(format!("{} must be greater than 0", key));
+            }
+        }
+        let urls = [
+            ("otlp_endpoint", &self.otlp_endpoint),
+            ("proxy_upstream", &self.proxy_upstream),
+        ];
+        for (key, url) in urls {
+            if let Some(url) = url.as_deref().filter(|u| !is_http_url(u)) {
+                problems.push(format!("{} '{}' is not an HTTP URL", key, url));
+            }
+        }
+        if let Some(s3) = &self.s3 {
+            if !is_http_url(&s3.endpoint) {
+                problems.push(format!("s3.endpoint '{}' is not an HTTP URL", s3.endpoint));
+            }
+            if s3.access_key.is_some() != s3.secret_key.is_some() {
+                problems.push("s3.access_key and s3.secret_key must be set together".to_owned());
+            }
+        }
+        if let Some(fetch) = &self.fetch {
+            for scheme in fetch
+                .schemes
+                .iter()
+                .filter(|s| !matches!(s.as_str(), "http" | "https"))
+            {
+                problems.push(format!("fetch.schemes: '{}' is not supported", scheme));
+            }
+            if fetch.timeout_secs == 0 {
+                problems.push("fetch.timeout_secs must be greater than 0".to_owned());
+            }
+        }
+        if let Some(tus) = &self.tus {
+            if tus.expiration_secs == 0 {
+                problems.push("tus.expiration_secs must be greater than 0".to_owned());
+            }
+            if tus.max_uploads == 0 {
+                problems.push("tus.max_uploads must be greater than 0".to_owned());
+            }
+        }
+        if let Some(Err(err)) = self.alert_syslog.as_deref().map(AlertSink::parse) {
+            problems.push(format!("alert_syslog: {}", err));
+        }
+        if let Some(Err(err)) = self.log_level.as_deref().map(EnvFilter::try_new) {
+            problems.push(format!("log_level: {}", err));
+        }
+        for (name, policy) in &self.policies {
+            if policy.max_file_size == Some(0) {
+                problems.push(format!(
+                    "policies.{}.max_file_size must be greater than 0",
+                    name
+                ));
+            }
+            for t in policy.content_types.iter().filter(|t| !t.contains('/')) {
+                problems.push(format!(
+                    "policies.{}.content_types: '{}' is not a MIME type",
+                    name, t
+                ));
+            }
+            for ext in policy.extensions.iter().filter(|e| e.starts_with('.')) {
+                problems.push(format!(
+                    "policies.{}.extensions: '{}' must not start with a dot",
+                    name, ext
+                ));
+            }
+        }
+        if let Some(name) = &self.watch.policy
+            && !self.policies.contains_key(name)
+        {
+            problems.push(format!("watch.policy: unknown policy '{}'", name));
+        }
+        for dir in self.watch.dirs.iter().filter(|d| !d.is_dir()) {
+            problems.push(format!(
+                "watch.dirs: '{}' is not a directory",
+                dir.display()
+            ));
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError::Invalid(problems)),
+        }
+    }
+
+    /// Reverts the settings which are only applied on startup to the values
+    /// of the `running` config, returning the keys which differed.
+    pub fn keep_restart_settings(&mut self, running: &AppConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        macro_rules! keep {
+            ($($key:ident),*) => {$(
+                if self.$key != running.$key {
+                    changed.push(stringify!($key));
+                    self.$key = running.$key.clone();
+                }
+            )*};
+        }
+        keep!(
+            port,
+            otlp_endpoint,
+            otlp_service_name,
+            log_format,
+            audit_log,
+            alert_syslog,
+            watch
+        );
+        changed
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Load(config::ConfigError),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Load(err) => write!(f, "failed to load config: {}", err),
+            Self::Invalid(problems) => write!(f, "invalid config:\n\t{}", problems.join("\n\t")),
+        }
+    }
+}
+
+impl From<config::ConfigError> for ConfigError {
+    fn from(err: config::ConfigError) -> Self {
+        Self::Load(err)
+    }
+}
+
+/// Where a top-level config value was set, unless it is the default.
+#[derive(Clone, Debug)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Env(var) => write!(f, "{}", var),
+            Self::Flag(key) => write!(f, "--{}", key.replace('_', "-")),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Sources(BTreeMap<String, Source>);
+
+impl Serialize for Sources {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, source)| (key, source.to_string())))
+    }
+}
+
+impl Sources {
+    pub fn set(&mut self, key: &str, source: Source) {
+        self.0.insert(key.to_owned(), source);
+    }
+}
+
+impl fmt::Display for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "\tall defaults");
+        }
+        let lines = self
+            .0
+            .iter()
+            .map(|(key, source)| format!("\t{}: {}", key, source))
+            .collect::<Vec<_>>();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Resolves the config file, see [`load`].
+pub fn config_path(explicit: Option<&Path>) -> Option<PathBuf> {
+    explicit
+        .map(Path::to_owned)
+        .or_else(|| DEFAULT_FILES.iter().map(PathBuf::from).find(|p| p.exists()))
+}
+
+/// Loads the config file, if any, overridden by `APP_*` environment
+/// variables. A file given explicitly must exist, otherwise the first of
+/// `Config.toml`, `Config.yaml` and `Config.yml` in the working directory is
+/// used. Unknown keys and malformed values are errors.
+pub fn load(path: Option<&Path>) -> Result<(AppConfig, Sources), ConfigError> {
+    let path = config_path(path);
+    let env = config::Environment::with_prefix(ENV_PREFIX)
+        .prefix_separator("_")
+        .separator("__")
+        .try_parsing(true)
+        .list_separator(",")
+        .with_list_parse_key("hashes");
+    let mut sources = Sources::default();
+    let mut builder = Config::builder();
+    if let Some(path) = &path {
+        let file = config::File::from(path.as_path());
+        for key in file.collect()?.keys() {
+            sources.set(key, Source::File(path.to_owned()));
+        }
+        builder = builder.add_source(file);
+    }
+    for key in env.collect()?.keys() {
+        let var = format!(
+            "{}_{}",
+            ENV_PREFIX,
+            key.replace('.', "__").to_ascii_uppercase()
+        );
+        sources.set(key, Source::Env(var));
+    }
+    let cfg = builder.add_source(env).build()?.try_deserialize()?;
+    Ok((cfg, sources))
+}
+
+/// Parses a byte count with an optional decimal (`kB`, `MB`, `GB`, `TB`) or
+/// binary (`KiB`, `MiB`, `GiB`, `TiB`) unit, e.g. `100MiB`. Units are case
+/// insensitive.
+pub fn parse_size<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let s = s.trim();
+    let (digits, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let value = digits
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => return Err(format!("unknown unit '{}' in size '{}'", other, s)),
+    };
+    value
+        .checked_mul(factor)
+        .and_then(|size| T::try_from(size).ok())
+        .ok_or_else(|| format!("size '{}' is out of range", s))
+}
+
+struct SizeVisitor;
+
+impl Visitor<'_> for SizeVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte count or a size such as \"100MiB\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+        parse_size(v).map_err(E::custom)
+    }
+}
+
+/// Accepts both plain integers and strings with units, see [`parse_size`].
+pub fn deserialize_size<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let size = d.deserialize_any(SizeVisitor)?;
+    T::try_from(size).map_err(|_| de::Error::custom(format!("size {} is out of range", size)))
+}
+
+pub fn deserialize_opt_size<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    deserialize_size(d).map(Some)
 }
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::hashes::Hashes;
+
+/// Target of the audit events, routed to the audit log only.
+pub const TARGET: &str = "audit";
+
+/// Identifies the request a scanned file belongs to.
+#[derive(Default)]
+pub struct Client {
+    pub request_id: Option<String>,
+    pub address: Option<String>,
+    pub forwarded_for: Option<String>,
+}
+
+pub struct AuditRecord<'a> {
+    pub client: &'a Client,
+    pub file_name: Option<&'a str>,
+    pub size: u64,
+    pub hashes: &'a Hashes,
+    pub verdict: &'a str,
+    pub signature: Option<&'a str>,
+    pub db_version: u32,
+    pub duration: Duration,
+}
+
+impl AuditRecord<'_> {
+    pub fn log(&self) {
+        tracing::info!(
+            target: TARGET,
+            request_id = self.client.request_id.as_deref(),
+            client_address = self.client.address.as_deref(),
+            forwarded_for = self.client.forwarded_for.as_deref(),
+            file_name = self.file_name,
+            size = self.size,
+            crc32 = self.hashes.crc32.as_deref(),
+            md5 = self.hashes.md5.as_deref(),
+            sha1 = self.hashes.sha1.as_deref(),
+            sha256 = self.hashes.sha256.as_deref(),
+            sha512 = self.hashes.sha512.as_deref(),
+            blake3 = self.hashes.blake3.as_deref(),
+            ssdeep = self.hashes.ssdeep.as_deref(),
+            tlsh = self.hashes.tlsh.as_ref().and_then(|t| t.as_deref()),
+            verdict = self.verdict,
+            signature = self.signature,
+            db_version = self.db_version,
+            duration_ms = self.duration.as_millis() as u64,
+            "scanned",
+        );
+    }
+}
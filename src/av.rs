@@ -1,5 +1,16 @@
 use chrono::{DateTime, Utc};
-use std::fmt;
+use std::{
+    fmt,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+const DATABASE_DIR: &str = "/var/lib/clamav";
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct AvContext {
     pub clamav_version: String,
@@ -24,16 +35,116 @@ impl fmt::Display for AvContext {
     }
 }
 
-pub async fn load_context() -> AvContext {
-    clamav_async::initialize().unwrap();
+#[derive(Debug)]
+pub enum AvError {
+    Initialize(String),
+    LoadDatabases(String),
+    Compile(String),
+    DatabaseInfo(String),
+}
+
+impl fmt::Display for AvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Initialize(err) => write!(f, "failed to initialize libclamav: {}", err),
+            Self::LoadDatabases(err) => write!(f, "failed to load databases: {}", err),
+            Self::Compile(err) => write!(f, "failed to compile engine: {}", err),
+            Self::DatabaseInfo(err) => write!(f, "failed to query database info: {}", err),
+        }
+    }
+}
+
+/// Holds the engine once it is compiled, or the error of the last attempt.
+#[derive(Default)]
+pub struct AvState {
+    ctx: OnceLock<AvContext>,
+    last_error: Mutex<Option<String>>,
+    pending: AtomicUsize,
+}
+
+impl AvState {
+    pub fn get(&self) -> Option<&AvContext> {
+        self.ctx.get()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Number of uploads currently being received or scanned.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn track(&self) -> PendingGuard<'_> {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        PendingGuard(&self.pending)
+    }
+}
+
+pub struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl From<AvContext> for AvState {
+    fn from(ctx: AvContext) -> Self {
+        let state = Self::default();
+        let _ = state.ctx.set(ctx);
+        state
+    }
+}
+
+pub async fn load_context() -> Result<AvContext, AvError> {
+    clamav_async::initialize().map_err(|err| AvError::Initialize(err.to_string()))?;
     let engine = clamav_async::engine::Engine::new();
-    let stats = engine.load_databases("/var/lib/clamav").await.unwrap();
-    engine.compile().await.unwrap();
-    AvContext {
+    let stats = engine
+        .load_databases(DATABASE_DIR)
+        .await
+        .map_err(|err| AvError::LoadDatabases(err.to_string()))?;
+    engine
+        .compile()
+        .await
+        .map_err(|err| AvError::Compile(err.to_string()))?;
+    let db_version = engine
+        .database_version()
+        .await
+        .map_err(|err| AvError::DatabaseInfo(err.to_string()))?;
+    let db_timestamp = engine
+        .database_timestamp()
+        .await
+        .map_err(|err| AvError::DatabaseInfo(err.to_string()))?;
+    Ok(AvContext {
         clamav_version: clamav_async::version(),
-        db_version: engine.database_version().await.unwrap(),
+        db_version,
         db_sig_count: stats.signature_count,
-        db_date: DateTime::<Utc>::from(engine.database_timestamp().await.unwrap()),
+        db_date: DateTime::<Utc>::from(db_timestamp),
         engine,
+    })
+}
+
+/// Loads the engine into `state`, retrying with exponential backoff while the
+/// database directory is still being populated, e.g. by a freshclam init
+/// container.
+pub async fn load_with_retry(state: &AvState) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match load_context().await {
+            Ok(ctx) => {
+                tracing::info!("Loaded context\n{}", ctx);
+                *state.last_error.lock().unwrap() = None;
+                let _ = state.ctx.set(ctx);
+                return;
+            }
+            Err(err) => {
+                tracing::warn!("{}, retrying in {:?}", err, backoff);
+                *state.last_error.lock().unwrap() = Some(err.to_string());
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+            }
+        }
     }
 }
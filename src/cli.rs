@@ -0,0 +1,317 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use clap::{Args, Parser, Subcommand};
+use tokio::{select, signal};
+
+use crate::{
+    alert::AlertSink,
+    app_config::{self, AppConfig, LogFormat, Sources},
+    av::{self, AvState},
+    controller::{self, AvResponse, ScanOptions},
+    hashes::HashAlgorithm,
+    reload::ConfigHandle,
+    telemetry, watch,
+};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Config file, defaults to `Config.toml`, `Config.yaml` or `Config.yml`
+    #[arg(long, short, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub overrides: Overrides,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the HTTP server (default)
+    Serve,
+    /// Scans local files and directories, printing the results as JSON
+    Scan(ScanArgs),
+    /// Scans files dropped into directories without running the HTTP server
+    Watch(WatchArgs),
+    /// Prints the service, libclamav and database versions
+    Version,
+    /// Prints the effective configuration and exits
+    CheckConfig,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Files or directories to scan, the latter recursively
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// List the members of archives
+    #[arg(long)]
+    members: bool,
+    /// Check the files against a named policy from the config
+    #[arg(long)]
+    policy: Option<String>,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Directories to watch instead of `watch.dirs` from the config
+    dirs: Vec<PathBuf>,
+}
+
+/// Config values taking precedence over `Config.toml` and `APP_*` variables.
+#[derive(Args)]
+pub struct Overrides {
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// Size in bytes or with a unit, e.g. `100MiB`
+    #[arg(long, global = true, value_parser = app_config::parse_size::<usize>)]
+    max_file_size: Option<usize>,
+    /// Size in bytes or with a unit, e.g. `1GiB`
+    #[arg(long, global = true, value_parser = app_config::parse_size::<usize>)]
+    max_request_size: Option<usize>,
+    #[arg(long, global = true)]
+    max_files_per_request: Option<usize>,
+    /// Comma separated list of digests, e.g. `sha256,blake3`
+    #[arg(long, global = true, value_delimiter = ',')]
+    hashes: Option<Vec<HashAlgorithm>>,
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    /// Log filter directives, e.g. `info,tower_http=debug`, instead of `RUST_LOG`
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    #[arg(long, global = true)]
+    audit_log: Option<String>,
+    #[arg(long, global = true)]
+    alert_syslog: Option<String>,
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+    /// `--enable-shutdown-endpoint=false` disables it if enabled in the config
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    enable_shutdown_endpoint: Option<bool>,
+    /// `--enable-admin-endpoint=false` disables it if enabled in the config
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    enable_admin_endpoint: Option<bool>,
+}
+
+impl Overrides {
+    /// Applies the flags to `cfg`, returning the keys which were set.
+    pub fn apply(&self, cfg: &mut AppConfig) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if let Some(port) = self.port {
+            cfg.port = port;
+            keys.push("port");
+        }
+        if let Some(size) = self.max_file_size {
+            cfg.max_file_size = size;
+            keys.push("max_file_size");
+        }
+        if let Some(size) = self.max_request_size {
+            cfg.max_request_size = size;
+            keys.push("max_request_size");
+        }
+        if let Some(count) = self.max_files_per_request {
+            cfg.max_files_per_request = count;
+            keys.push("max_files_per_request");
+        }
+        if let Some(hashes) = &self.hashes {
+            cfg.hashes = hashes.clone();
+            keys.push("hashes");
+        }
+        if let Some(format) = self.log_format {
+            cfg.log_format = format;
+            keys.push("log_format");
+        }
+        if self.log_level.is_some() {
+            cfg.log_level = self.log_level.clone();
+            keys.push("log_level");
+        }
+        if self.audit_log.is_some() {
+            cfg.audit_log = self.audit_log.clone();
+            keys.push("audit_log");
+        }
+        if self.alert_syslog.is_some() {
+            cfg.alert_syslog = self.alert_syslog.clone();
+            keys.push("alert_syslog");
+        }
+        if self.otlp_endpoint.is_some() {
+            cfg.otlp_endpoint = self.otlp_endpoint.clone();
+            keys.push("otlp_endpoint");
+        }
+        if let Some(enabled) = self.enable_shutdown_endpoint {
+            cfg.enable_shutdown_endpoint = enabled;
+            keys.push("enable_shutdown_endpoint");
+        }
+        if let Some(enabled) = self.enable_admin_endpoint {
+            cfg.enable_admin_endpoint = enabled;
+            keys.push("enable_admin_endpoint");
+        }
+        keys
+    }
+}
+
+/// Scans the given paths and prints the `AvResponse` to stdout. Exits like
+/// `clamscan`: 0 if all files are clean, 1 if any file is infected or
+/// rejected, 2 on errors.
+pub async fn scan(cfg: &AppConfig, args: ScanArgs) -> ExitCode {
+    let policy = match &args.policy {
+        None => None,
+        Some(name) => match cfg.policies.get(name) {
+            Some(policy) => Some(policy),
+            None => {
+                eprintln!("Unknown policy '{}'", name);
+                return ExitCode::from(2);
+            }
+        },
+    };
+    let ctx = match av::load_context().await {
+        Ok(ctx) => ctx,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    };
+    let mut files = Vec::new();
+    for path in &args.paths {
+        if let Err(err) = collect_files(path, &mut files) {
+            eprintln!("{}: {}", path.display(), err);
+            return ExitCode::from(2);
+        }
+    }
+    let options = ScanOptions {
+        policy,
+        algorithms: cfg.hashes.clone(),
+        members: args.members,
+    };
+    let mut results = Vec::new();
+    let mut failed = false;
+    for file in files {
+        let name = file.to_string_lossy();
+        match controller::scan_file(&ctx, &file, &name, &options).await {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                eprintln!("{}: {}", file.display(), err);
+                failed = true;
+            }
+        }
+    }
+    let response = AvResponse::new(&ctx, results);
+    match serde_json::to_string_pretty(&response) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    }
+    if failed {
+        ExitCode::from(2)
+    } else if response.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+/// Runs the directory watcher alone until interrupted.
+pub async fn watch(mut cfg: AppConfig, sources: Sources, args: WatchArgs) -> ExitCode {
+    if !args.dirs.is_empty() {
+        cfg.watch.dirs = args.dirs;
+    }
+    if cfg.watch.dirs.is_empty() {
+        eprintln!("No directories to watch, set watch.dirs or pass them as arguments");
+        return ExitCode::from(2);
+    }
+    if let Some(dir) = cfg.watch.dirs.iter().find(|d| !d.is_dir()) {
+        eprintln!("{} is not a directory", dir.display());
+        return ExitCode::from(2);
+    }
+    let alerts = match cfg.alert_syslog.as_deref().map(AlertSink::parse) {
+        Some(Ok(sink)) => Some(Arc::new(sink)),
+        Some(Err(err)) => {
+            eprintln!("alert_syslog: {}", err);
+            return ExitCode::from(2);
+        }
+        None => None,
+    };
+    let _telemetry = telemetry::init(&cfg);
+    let av = Arc::new(AvState::default());
+    tokio::spawn({
+        let av = Arc::clone(&av);
+        async move { av::load_with_retry(&av).await }
+    });
+    let config = Arc::new(ConfigHandle::new(cfg, sources));
+    select! {
+        res = watch::run(config, av, alerts) => match res {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{}", err);
+                ExitCode::FAILURE
+            }
+        },
+        _ = signal::ctrl_c() => ExitCode::SUCCESS,
+    }
+}
+
+pub async fn version() -> ExitCode {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    match av::load_context().await {
+        Ok(ctx) => {
+            println!("{}", ctx);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints the config and where its values came from. Invalid configs are
+/// rejected before any subcommand runs.
+pub fn check_config(cfg: &AppConfig, sources: &Sources) -> ExitCode {
+    println!("Config\n{}", cfg);
+    println!("Sources\n{}", sources);
+    ExitCode::SUCCESS
+}
+
+/// Appends `path` if it is a file or all files below it if it is a directory.
+/// Symlinks to directories are only followed if given directly, so that a
+/// link loop cannot recurse forever.
+pub fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    collect(path, files, true)
+}
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>, top: bool) -> std::io::Result<()> {
+    let link = std::fs::symlink_metadata(path)?.file_type().is_symlink();
+    if !std::fs::metadata(path)?.is_dir() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    if link && !top {
+        eprintln!("{}: skipping symlinked directory", path.display());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        collect(&entry, files, false)?;
+    }
+    Ok(())
+}
@@ -1,20 +1,43 @@
 use axum::{
     Extension, Json,
-    extract::{Multipart, multipart::Field},
+    extract::{
+        ConnectInfo, FromRequestParts, Multipart, Query,
+        multipart::{Field, MultipartRejection},
+    },
+    http::{HeaderMap, request::Parts},
     response::Html,
 };
 use chrono::{SecondsFormat, Utc};
 use clamav_async::fmap::Fmap;
-use digest::Digest;
 use hyper::StatusCode;
-use serde::Serialize;
-use std::{io::Write, os::unix::fs::MetadataExt, sync::Arc};
-use tokio::{fs::File, io::AsyncReadExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncReadExt;
 use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{app_config::AppConfig, av::AvContext};
+use crate::{
+    alert::{AlertSink, Detection},
+    app_config::AppConfig,
+    audit::{AuditRecord, Client},
+    av::{AvContext, AvState},
+    encoding::{Base64Decoder, BodyEncoding, Decompressed},
+    error::{ApiError, ErrorKind, Problem},
+    fetch::Origin,
+    filetype::{self, Detector, FileType},
+    hashes::{self, HashAlgorithm, Hashes},
+    openapi::UploadForm,
+    policy::Policy,
+    scan_metrics,
+};
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct AvResponse {
     #[serde(rename = "avVersion")]
     av_version: String,
@@ -25,21 +48,223 @@ pub struct AvResponse {
     #[serde(rename = "dbDate")]
     db_date: String,
     results: Vec<AvResult>,
+    #[serde(rename = "bodyEncoding", skip_serializing_if = "Option::is_none")]
+    body_encoding: Option<BodyEncoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
 }
 
-#[derive(Serialize)]
+impl AvResponse {
+    pub fn new(ctx: &AvContext, results: Vec<AvResult>) -> Self {
+        Self {
+            av_version: ctx.clamav_version.to_owned(),
+            db_version: ctx.db_version,
+            db_sig_count: ctx.db_sig_count,
+            db_date: ctx.db_date.to_rfc3339_opts(SecondsFormat::Millis, true),
+            results,
+            body_encoding: None,
+            warning: None,
+        }
+    }
+
+    /// Reports a failure which happened after the scan and did not change
+    /// its results.
+    pub fn with_warning(mut self, warning: String) -> Self {
+        self.warning = Some(warning);
+        self
+    }
+
+    pub fn results(&self) -> &[AvResult] {
+        &self.results
+    }
+
+    /// Whether no file was found infected or rejected.
+    pub fn is_clean(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| matches!(r.result, "CLEAN" | "WHITELISTED"))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct AvResult {
+    /// File name of the part, or its field name.
     name: Option<String>,
     size: u64,
-    crc32: String,
-    md5: String,
-    sha256: String,
+    /// Size of the content before base64 decoding.
+    #[serde(rename = "encodedSize", skip_serializing_if = "Option::is_none")]
+    encoded_size: Option<u64>,
+    #[serde(flatten)]
+    hashes: Hashes,
     #[serde(rename = "contentType")]
     content_type: Option<&'static str>,
+    #[serde(rename = "typeDescription")]
+    type_description: Option<&'static str>,
+    #[serde(rename = "typeDetector")]
+    type_detector: Option<Detector>,
     #[serde(rename = "dateScanned")]
     date_scanned: String,
+    /// `CLEAN`, `WHITELISTED`, `VIRUS` or `REJECTED` by a policy.
+    #[schema(example = "CLEAN")]
     result: &'static str,
     signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(rename = "typeMismatch", skip_serializing_if = "Option::is_none")]
+    type_mismatch: Option<TypeMismatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<AvMember>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<Origin>,
+}
+
+impl AvResult {
+    pub fn result(&self) -> &'static str {
+        self.result
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    pub fn date_scanned(&self) -> &str {
+        &self.date_scanned
+    }
+
+    pub fn set_encoded_size(&mut self, size: u64) {
+        self.encoded_size = Some(size);
+    }
+
+    /// Records where the content was fetched from.
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = Some(origin);
+    }
+
+    fn log_audit(&self, client: &Client, db_version: u32, duration: Duration) {
+        AuditRecord {
+            client,
+            file_name: self.name.as_deref(),
+            size: self.size,
+            hashes: &self.hashes,
+            verdict: self.result,
+            signature: self.signature.as_deref(),
+            db_version,
+            duration,
+        }
+        .log();
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TypeMismatch {
+    #[serde(rename = "declaredType")]
+    declared_type: Option<String>,
+    #[serde(rename = "declaredExtension")]
+    declared_extension: Option<String>,
+    #[serde(rename = "detectedType")]
+    detected_type: &'static str,
+    #[serde(rename = "detectedExtension")]
+    detected_extension: Option<&'static str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AvMember {
+    name: Option<String>,
+    path: String,
+    size: usize,
+    #[serde(rename = "fileType")]
+    file_type: String,
+    detected: bool,
+    children: Vec<AvMember>,
+}
+
+/// Client supplied metadata of a scanned file.
+#[derive(Clone, Copy, Default)]
+pub struct PartInfo<'a> {
+    pub name: Option<&'a str>,
+    pub file_name: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+}
+
+impl<'a> From<&'a Field<'_>> for PartInfo<'a> {
+    fn from(field: &'a Field<'_>) -> Self {
+        Self {
+            name: field.name(),
+            file_name: field.file_name(),
+            content_type: field.content_type(),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadQuery {
+    /// Lists the members of archives as `children`.
+    #[serde(default)]
+    members: bool,
+    /// Name of a configured policy.
+    policy: Option<String>,
+    /// Comma separated hash algorithms, e.g. `sha1,sha256,blake3`.
+    hashes: Option<String>,
+}
+
+/// Limit of the small JSON requests which only point to the content to scan.
+pub const JSON_REQUEST_LIMIT: usize = 16 * 1024;
+
+/// Extracted like `Query`, but an invalid query string is rejected as
+/// problem+json like every other client error.
+impl<S: Send + Sync> FromRequestParts<S> for UploadQuery {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        match Query::<Self>::from_request_parts(parts, state).await {
+            Ok(Query(query)) => Ok(query),
+            Err(err) => {
+                let request_id = parts.headers.get("x-request-id");
+                Err(ApiError::from(ErrorKind::InvalidRequest(err.body_text()))
+                    .with_request_id(request_id.and_then(|v| v.to_str().ok())))
+            }
+        }
+    }
+}
+
+/// Policy, digests and member listing to apply to a scan.
+pub struct ScanOptions<'a> {
+    pub policy: Option<&'a Policy>,
+    pub algorithms: Vec<HashAlgorithm>,
+    pub members: bool,
+}
+
+/// Everything needed to scan the content of a request and report the result.
+pub struct ScanRequest<'a> {
+    pub cfg: &'a AppConfig,
+    pub ctx: &'a AvContext,
+    pub query: &'a UploadQuery,
+    pub client: &'a Client,
+    pub alerts: Option<&'a AlertSink>,
+}
+
+impl UploadQuery {
+    /// Resolves the named policy and the hash list against the config.
+    pub fn options<'a>(&self, cfg: &'a AppConfig) -> Result<ScanOptions<'a>, ApiError> {
+        let policy = match &self.policy {
+            None => None,
+            Some(name) => Some(
+                cfg.policies
+                    .get(name)
+                    .ok_or_else(|| ErrorKind::UnknownPolicy(name.to_owned()))?,
+            ),
+        };
+        let algorithms = match &self.hashes {
+            None => cfg.hashes.clone(),
+            Some(list) => hashes::parse_list(list).map_err(ErrorKind::InvalidHashes)?,
+        };
+        Ok(ScanOptions {
+            policy,
+            algorithms,
+            members: self.members,
+        })
+    }
 }
 
 const INDEX_HTML: &'static [u8] = include_bytes!("index.html");
@@ -65,125 +290,477 @@ pub async fn shutdown(
     }
 }
 
+/// Scans all file parts of a multipart form.
+#[utoipa::path(
+    post,
+    path = "/upload",
+    tag = "scan",
+    params(UploadQuery),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "One result per part", body = AvResponse),
+        (status = "4XX", description = "Invalid request or limit exceeded", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Scan engine failed or not loaded yet", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 pub async fn upload(
-    Extension(ctx): Extension<Arc<AvContext>>,
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    query: UploadQuery,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    decompressed: Option<Extension<Decompressed>>,
+    headers: HeaderMap,
+    mp: Result<Multipart, MultipartRejection>,
+) -> Result<Json<AvResponse>, ApiError> {
+    let client = audit_client(&headers, connect_info);
+    let mp = mp.map_err(|err| ApiError::from(err).with_request_id(client.request_id.as_deref()))?;
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => {
+            let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+            receive(&cfg, ctx, &query, &client, alerts, mp).await
+        }
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    result
+        .map(|mut response| {
+            if let Some(Extension(decompressed)) = decompressed {
+                response.body_encoding = Some(decompressed.report());
+            }
+            response
+        })
+        .map(Json)
+        .map_err(|err| err.with_request_id(client.request_id.as_deref()))
+}
+
+/// Identifies the request for the audit log.
+pub fn audit_client(
+    headers: &HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Client {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    Client {
+        request_id: header("x-request-id").map(|v| v.to_owned()),
+        address: connect_info.map(|Extension(ConnectInfo(addr))| addr.ip().to_string()),
+        forwarded_for: header("x-forwarded-for").map(|v| v.to_owned()),
+    }
+}
+
+/// Writes the audit record and sends an alert on detection.
+pub async fn report(
+    result: &AvResult,
+    ctx: &AvContext,
+    client: &Client,
+    alerts: Option<&AlertSink>,
+    duration: Duration,
+) {
+    result.log_audit(client, ctx.db_version, duration);
+    if let Some((sink, signature)) = alerts.zip(result.signature.as_deref()) {
+        let detection = Detection {
+            signature,
+            sha256: result.hashes.sha256.as_deref(),
+            file_name: result.name.as_deref(),
+            size: result.size,
+            client_address: client.address.as_deref(),
+        };
+        if let Err(err) = sink.send(&detection).await {
+            tracing::warn!("Failed to send detection alert: {}", err);
+        }
+    }
+}
+
+/// Receives, hashes and scans all parts of a multipart request.
+pub async fn receive(
+    cfg: &AppConfig,
+    ctx: &AvContext,
+    query: &UploadQuery,
+    client: &Client,
+    alerts: Option<&AlertSink>,
     mut mp: Multipart,
-) -> Result<Json<AvResponse>, (StatusCode, String)> {
+) -> Result<AvResponse, ApiError> {
+    let options = query.options(cfg)?;
     let mut results = Vec::new();
-    while let Some(mut field) = mp.next_field().await.map_err(map_mp_error_to_400)? {
-        let mut tmp = tempfile::Builder::new()
-            .rand_bytes(12)
-            .tempfile()
-            .map_err(map_io_error_to_500)?;
-        let mut size = 0;
-        let mut crc32 = crc_fast::Digest::new(crc_fast::CrcAlgorithm::Crc32IsoHdlc);
-        let mut md5 = md5::Md5::new();
-        let mut sha256 = sha2::Sha256::new();
-        while let Some(chunk) = field.chunk().await.map_err(map_mp_error_to_400)? {
-            size += tmp.write(&chunk).map_err(map_io_error_to_500)? as u64;
-            crc32.update(&chunk);
-            md5.update(&chunk);
-            sha256.update(&chunk);
+    let mut total = 0;
+    while let Some(mut field) = mp.next_field().await? {
+        let index = results.len();
+        if index >= cfg.max_files_per_request {
+            let err = ApiError::from(ErrorKind::TooManyFiles(cfg.max_files_per_request));
+            return Err(err.with_part(index, &field));
         }
-        tmp.as_file().sync_data().map_err(map_io_error_to_500)?;
-        results.push(
-            scan(
-                &ctx,
-                &field,
-                &tmp,
-                size,
-                format!("{:08x?}", crc32.finalize()),
-                const_hex::encode(md5.finalize()),
-                const_hex::encode(sha256.finalize()),
-            )
+        let (tmp, size, hashes, encoded_size) =
+            receive_part(cfg, &mut field, index, &mut total, &options.algorithms).await?;
+        let started = Instant::now();
+        let part = PartInfo::from(&field);
+        let mut result = scan(ctx, part, tmp.path(), size, hashes, &options)
             .await
-            .map_err(map_io_error_to_500)?,
-        );
+            .map_err(|err| err.with_part(index, &field))?;
+        if let Some(encoded_size) = encoded_size {
+            result.set_encoded_size(encoded_size);
+        }
+        report(&result, ctx, client, alerts, started.elapsed()).await;
+        results.push(result);
     }
-    Ok(Json(AvResponse {
-        av_version: ctx.clamav_version.to_owned(),
-        db_version: ctx.db_version,
-        db_sig_count: ctx.db_sig_count,
-        db_date: ctx.db_date.to_rfc3339_opts(SecondsFormat::Millis, true),
-        results,
-    }))
+    Ok(AvResponse::new(ctx, results))
 }
 
-#[inline]
-async fn scan(
+#[tracing::instrument(
+    skip_all,
+    fields(index, name = field.file_name().or(field.name()), size = tracing::field::Empty)
+)]
+async fn receive_part(
+    cfg: &AppConfig,
+    field: &mut Field<'_>,
+    index: usize,
+    total: &mut usize,
+    algorithms: &[HashAlgorithm],
+) -> Result<(tempfile::NamedTempFile, u64, Hashes, Option<u64>), ApiError> {
+    let mut spool = Spool::new(algorithms)?;
+    let base64 = is_base64(field);
+    let mut decoder = base64.then(Base64Decoder::default);
+    let mut encoded_size = 0;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| ApiError::from(err).with_part(index, field))?
+    {
+        encoded_size += chunk.len() as u64;
+        let decoded;
+        let data = match decoder.as_mut() {
+            Some(decoder) => {
+                decoded = decoder
+                    .update(&chunk)
+                    .map_err(|err| invalid_base64(err, index, field))?;
+                &decoded[..]
+            }
+            None => &chunk[..],
+        };
+        append_part(cfg, &mut spool, total, data).map_err(|err| err.with_part(index, field))?;
+    }
+    if let Some(decoder) = decoder {
+        let decoded = decoder
+            .finish()
+            .map_err(|err| invalid_base64(err, index, field))?;
+        append_part(cfg, &mut spool, total, &decoded).map_err(|err| err.with_part(index, field))?;
+    }
+    tracing::Span::current().record("size", spool.size());
+    let encoded_size = base64.then_some(encoded_size);
+    let (tmp, size, hashes) = spool.finish()?;
+    Ok((tmp, size, hashes, encoded_size))
+}
+
+/// Whether the part declares `Content-Transfer-Encoding: base64`, which some
+/// mail-oriented clients still send.
+fn is_base64(field: &Field<'_>) -> bool {
+    field
+        .headers()
+        .get("content-transfer-encoding")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("base64"))
+}
+
+fn invalid_base64(err: base64::DecodeError, index: usize, field: &Field<'_>) -> ApiError {
+    let err = ErrorKind::InvalidRequest(format!("invalid base64 content: {}", err));
+    ApiError::from(err).with_part(index, field)
+}
+
+/// Checks the limits against the decoded content before spooling it.
+pub fn append_part(
+    cfg: &AppConfig,
+    spool: &mut Spool,
+    total: &mut usize,
+    data: &[u8],
+) -> Result<(), ApiError> {
+    if spool.size() + data.len() as u64 > cfg.max_file_size as u64 {
+        return Err(ErrorKind::FileTooLarge(cfg.max_file_size).into());
+    }
+    if *total + data.len() > cfg.max_request_size {
+        return Err(ErrorKind::RequestTooLarge(cfg.max_request_size).into());
+    }
+    spool.write(data)?;
+    *total += data.len();
+    Ok(())
+}
+
+/// Writes received content to a temp file while hashing it.
+pub struct Spool {
+    tmp: tempfile::NamedTempFile,
+    size: u64,
+    hasher: hashes::Hasher,
+    write_duration: Duration,
+}
+
+impl Spool {
+    pub fn new(algorithms: &[HashAlgorithm]) -> Result<Self, ApiError> {
+        Ok(Self {
+            tmp: tempfile::Builder::new().rand_bytes(12).tempfile()?,
+            size: 0,
+            hasher: hashes::Hasher::new(algorithms),
+            write_duration: Duration::ZERO,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), ApiError> {
+        let started = Instant::now();
+        self.tmp.write_all(chunk)?;
+        self.write_duration += started.elapsed();
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Syncs the temp file to disk and finalizes the digests.
+    pub fn finish(self) -> Result<(tempfile::NamedTempFile, u64, Hashes), ApiError> {
+        let started = Instant::now();
+        self.tmp.as_file().sync_data()?;
+        scan_metrics::record_write(self.write_duration + started.elapsed());
+        let hasher = self.hasher;
+        let hashes = tracing::info_span!("hash").in_scope(|| hasher.finalize());
+        Ok((self.tmp, self.size, hashes))
+    }
+}
+
+/// Hashes and scans a local file in place, e.g. for the `scan` subcommand.
+pub async fn scan_file(
     ctx: &AvContext,
-    field: &Field<'_>,
-    tmp: &tempfile::NamedTempFile,
+    path: &Path,
+    file_name: &str,
+    options: &ScanOptions<'_>,
+) -> Result<AvResult, ApiError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = hashes::Hasher::new(&options.algorithms);
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    let hashes = hasher.finalize();
+    let part = PartInfo {
+        name: None,
+        file_name: Some(file_name),
+        content_type: None,
+    };
+    scan(ctx, part, path, size, hashes, options).await
+}
+
+#[tracing::instrument(
+    name = "clamav_scan",
+    skip_all,
+    fields(size, verdict = tracing::field::Empty, signature = tracing::field::Empty)
+)]
+pub async fn scan(
+    ctx: &AvContext,
+    part: PartInfo<'_>,
+    path: &Path,
     size: u64,
-    crc32: String,
-    md5: String,
-    sha256: String,
-) -> Result<AvResult, std::io::Error> {
-    let name = field.file_name().or(field.name()).map(|f| f.to_string());
-    let path = tmp
-        .path()
+    hashes: Hashes,
+    options: &ScanOptions<'_>,
+) -> Result<AvResult, ApiError> {
+    let policy = options.policy;
+    let name = part.file_name.or(part.name).map(|f| f.to_string());
+    let path = path
         .to_str()
         .ok_or_else(|| std::io::Error::other("invalid path string"))?;
-    let content_type = detect_type(path).await?;
     let target = Fmap::from_file(std::fs::File::open(path)?, 0, size as usize, true);
-    let settings = clamav_async::scan_settings::ScanSettings::default();
+    let settings = match policy {
+        Some(p) if !p.allow_macros => clamav_async::scan_settings::ScanSettingsBuilder::new()
+            .block_macros()
+            .build(),
+        _ => clamav_async::scan_settings::ScanSettings::default(),
+    };
+    let started = Instant::now();
     let mut stream = ctx
         .engine
         .scan(target, Some(path), settings)
-        .map_err(|err| std::io::Error::other(err))?;
-    let r = poll_result(&mut stream).await?;
+        .map_err(ApiError::engine)?;
+    let (r, inspected) = poll_result(&mut stream).await?;
+    let scan_duration = started.elapsed();
+    let clamav_type = inspected
+        .iter()
+        .find(|i| i.recursion_level == 0)
+        .map(|i| i.file_type.to_owned());
+    let detected = filetype::detect(path, clamav_type.as_deref()).await?;
+    let content_type = detected.map(|t| t.mime_type);
+    let type_mismatch = detected
+        .filter(FileType::is_conclusive)
+        .and_then(|t| detect_mismatch(part.file_name, part.content_type, t));
+    let children = options.members.then(|| {
+        let detected = matches!(r, clamav_async::engine::ScanResult::Virus(_));
+        build_members(inspected, detected)
+    });
+    let (mut result, mut signature) = match r {
+        clamav_async::engine::ScanResult::Clean => ("CLEAN", None),
+        clamav_async::engine::ScanResult::Whitelisted => ("WHITELISTED", None),
+        clamav_async::engine::ScanResult::Virus(sig) => ("VIRUS", Some(sig.to_owned())),
+    };
+    let mut reason = None;
+    if let Some(p) = policy {
+        if signature
+            .as_deref()
+            .is_some_and(|sig| sig.starts_with(crate::policy::MACROS_SIGNATURE))
+        {
+            (result, signature) = ("REJECTED", None);
+            reason = Some("macros are not allowed".to_owned());
+        } else if result != "VIRUS" {
+            reason = p.check(part.file_name, part.content_type, content_type, size);
+            if reason.is_some() {
+                result = "REJECTED";
+            }
+        }
+    }
+    let span = tracing::Span::current();
+    span.record("verdict", result);
+    span.record("signature", signature.as_deref());
+    scan_metrics::record_scan(
+        result,
+        signature.as_deref(),
+        content_type,
+        size,
+        scan_duration,
+    );
     return Ok(AvResult {
         name,
         size,
-        crc32,
-        md5,
-        sha256,
+        encoded_size: None,
+        hashes,
         content_type,
+        type_description: detected.map(|t| t.description),
+        type_detector: detected.map(|t| t.detector),
         date_scanned: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-        result: match r {
-            clamav_async::engine::ScanResult::Clean => "CLEAN",
-            clamav_async::engine::ScanResult::Whitelisted => "WHITELISTED",
-            clamav_async::engine::ScanResult::Virus(_) => "VIRUS",
-        },
-        signature: match r {
-            clamav_async::engine::ScanResult::Clean => None,
-            clamav_async::engine::ScanResult::Whitelisted => None,
-            clamav_async::engine::ScanResult::Virus(sig) => Some(sig.to_owned()),
-        },
+        result,
+        signature,
+        reason,
+        type_mismatch,
+        children,
+        origin: None,
     });
 }
 
-#[inline]
-async fn detect_type(path: &str) -> Result<Option<&'static str>, std::io::Error> {
-    const LIMIT: u64 = 8192;
-    let file = File::open(path).await?;
-    let size = std::cmp::min(LIMIT, file.metadata().await?.size());
-    let mut buf = Vec::with_capacity(size as usize);
-    file.take(LIMIT).read_to_end(&mut buf).await?;
-    Ok(infer::get(buf.as_slice()).map(|t| t.mime_type()))
+/// Compares the declared content type and the filename extension with the
+/// detected type. `application/octet-stream` is not considered a declaration.
+fn detect_mismatch(
+    file_name: Option<&str>,
+    declared_type: Option<&str>,
+    detected: FileType,
+) -> Option<TypeMismatch> {
+    const EXTENSION_ALIASES: &[(&str, &str)] = &[
+        ("jpeg", "jpg"),
+        ("jpe", "jpg"),
+        ("tiff", "tif"),
+        ("htm", "html"),
+        ("mpeg", "mpg"),
+    ];
+    let declared_extension = file_name
+        .and_then(|n| n.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    let declared_type = declared_type
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| t != "application/octet-stream");
+    let canonical = |ext: &str| {
+        EXTENSION_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == ext)
+            .map_or(ext, |(_, canonical)| *canonical)
+            .to_owned()
+    };
+    let extension_mismatch = declared_extension
+        .as_deref()
+        .zip(detected.extension)
+        .is_some_and(|(ext, expected)| canonical(ext) != canonical(expected));
+    let type_mismatch = declared_type
+        .as_deref()
+        .is_some_and(|t| t != detected.mime_type);
+    (extension_mismatch || type_mismatch).then_some(TypeMismatch {
+        declared_type,
+        declared_extension,
+        detected_type: detected.mime_type,
+        detected_extension: detected.extension,
+    })
 }
 
 #[inline]
 async fn poll_result(
     rs: &mut ReceiverStream<clamav_async::engine::ScanEvent>,
-) -> Result<clamav_async::engine::ScanResult, std::io::Error> {
+) -> Result<
+    (
+        clamav_async::engine::ScanResult,
+        Vec<clamav_async::engine::FileInspect>,
+    ),
+    ApiError,
+> {
+    let mut inspected = Vec::new();
     while let Some(event) = rs.next().await {
         match event {
             clamav_async::engine::ScanEvent::Result(r) => {
-                return r.map_err(|err| std::io::Error::other(err));
+                return r.map(|r| (r, inspected)).map_err(ApiError::engine);
             }
+            clamav_async::engine::ScanEvent::FileInspect(i) => inspected.push(i),
             _ => continue,
         }
     }
-    return Err(std::io::Error::other("Could not retrieve scan result"));
+    return Err(ApiError::engine("Could not retrieve scan result"));
 }
 
-#[inline]
-fn map_mp_error_to_400(err: axum::extract::multipart::MultipartError) -> (StatusCode, String) {
-    (StatusCode::BAD_REQUEST, err.to_string())
-}
-
-#[inline]
-fn map_io_error_to_500(err: std::io::Error) -> (StatusCode, String) {
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+/// Turns the flat sequence of file inspection events into a member tree.
+///
+/// libclamav reports each layer before scanning it and stops at the first
+/// detection, so on a `VIRUS` verdict the last inspected layer is the one
+/// which triggered it. The root layer (recursion level 0) is the upload itself
+/// and is not listed.
+fn build_members(
+    inspected: Vec<clamav_async::engine::FileInspect>,
+    detected: bool,
+) -> Vec<AvMember> {
+    let mut roots = Vec::new();
+    let mut stack: Vec<AvMember> = Vec::new();
+    for i in inspected {
+        let level = i.recursion_level as usize;
+        if level == 0 {
+            continue;
+        }
+        while stack.len() >= level {
+            let member = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(member),
+                None => roots.push(member),
+            }
+        }
+        let path = i
+            .ancestors
+            .iter()
+            .skip(1)
+            .chain(std::iter::once(&i.file_name))
+            .map(|n| n.as_deref().unwrap_or("?"))
+            .collect::<Vec<_>>()
+            .join("/");
+        stack.push(AvMember {
+            name: i.file_name,
+            path,
+            size: i.file_size,
+            file_type: i.file_type,
+            detected: false,
+            children: Vec::new(),
+        });
+    }
+    if let Some(last) = stack.last_mut() {
+        last.detected = detected;
+    }
+    while let Some(member) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(member),
+            None => roots.push(member),
+        }
+    }
+    roots
 }
@@ -0,0 +1,207 @@
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZstdDecoder};
+use axum::{
+    Extension,
+    body::Body,
+    extract::Request,
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{
+    Engine, alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+};
+use serde::Serialize;
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+use utoipa::ToSchema;
+
+use crate::{
+    app_config::AppConfig,
+    error::{ApiError, ErrorKind},
+};
+
+/// Accepts base64 with or without padding, as senders differ.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Room for the multipart or JSON framing of each file in a decoded body.
+const FRAMING: usize = 64 * 1024;
+
+/// Sizes of a request body before and after decompression, available as an
+/// extension to the handlers once the body has been consumed.
+#[derive(Clone)]
+pub struct Decompressed(Arc<Sizes>);
+
+struct Sizes {
+    encoding: &'static str,
+    encoded: AtomicU64,
+    decoded: AtomicU64,
+    /// Why decoding stopped, which replaces the response of the handler as
+    /// it only sees a failed body.
+    failure: Mutex<Option<ErrorKind>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BodyEncoding {
+    #[serde(rename = "contentEncoding")]
+    content_encoding: &'static str,
+    #[serde(rename = "encodedSize")]
+    encoded_size: u64,
+    #[serde(rename = "decodedSize")]
+    decoded_size: u64,
+}
+
+impl Decompressed {
+    pub fn report(&self) -> BodyEncoding {
+        BodyEncoding {
+            content_encoding: self.0.encoding,
+            encoded_size: self.0.encoded.load(Ordering::Relaxed),
+            decoded_size: self.0.decoded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Limit of a decoded body: `max_request_size`, or the files allowed per
+/// request at one and a half times `max_file_size` for base64, with their
+/// framing, whichever is lower. `None` if neither is configured.
+fn decoded_limit(cfg: &AppConfig) -> Option<usize> {
+    let file = cfg
+        .max_file_size
+        .saturating_add(cfg.max_file_size / 2)
+        .saturating_add(FRAMING);
+    let limit = cfg
+        .max_request_size
+        .min(file.saturating_mul(cfg.max_files_per_request));
+    (limit != usize::MAX).then_some(limit)
+}
+
+/// Decodes request bodies with `Content-Encoding: gzip`, `br` or `zstd`
+/// while streaming, so handlers hash and scan the original content. The
+/// decoded body is cut off beyond the [`decoded_limit`], which bounds
+/// decompression bombs also where handlers skip content, and responds with
+/// `413`, or `400` if the body cannot be decoded. Other encodings, and any
+/// encoding without a limit, are rejected with `415`.
+pub async fn decompress(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(value) = req.headers().get(header::CONTENT_ENCODING) else {
+        return next.run(req).await;
+    };
+    let encoding = match value.to_str().map(|v| v.trim().to_ascii_lowercase()) {
+        Ok(encoding) if encoding == "identity" => return next.run(req).await,
+        Ok(encoding) => encoding,
+        Err(_) => String::new(),
+    };
+    let encoding = match encoding.as_str() {
+        "gzip" | "x-gzip" => "gzip",
+        "br" => "br",
+        "zstd" => "zstd",
+        _ => {
+            let err = ErrorKind::UnsupportedEncoding(value.to_str().unwrap_or_default().to_owned());
+            return ApiError::from(err).into_response();
+        }
+    };
+    let Some(limit) = decoded_limit(&cfg) else {
+        return ApiError::from(ErrorKind::UnboundedEncoding(encoding)).into_response();
+    };
+    let (mut parts, body) = req.into_parts();
+    let request_id = parts
+        .headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let sizes = Arc::new(Sizes {
+        encoding,
+        encoded: AtomicU64::new(0),
+        decoded: AtomicU64::new(0),
+        failure: Mutex::new(None),
+    });
+    parts.extensions.insert(Decompressed(Arc::clone(&sizes)));
+
+    let counter = Arc::clone(&sizes);
+    let encoded = body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        counter
+            .encoded
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        Ok::<_, std::io::Error>(chunk)
+    });
+    let reader = StreamReader::new(encoded);
+    let decoder: Pin<Box<dyn AsyncRead + Send>> = match encoding {
+        "gzip" => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::pin(decoder)
+        }
+        "br" => Box::pin(BrotliDecoder::new(reader)),
+        _ => Box::pin(ZstdDecoder::new(reader)),
+    };
+    let state = Arc::clone(&sizes);
+    let decoded = ReaderStream::new(decoder).map(move |chunk| {
+        let fail = |kind: ErrorKind| {
+            let err = std::io::Error::other(kind.to_string());
+            *state.failure.lock().unwrap() = Some(kind);
+            err
+        };
+        let chunk = chunk.map_err(|err| {
+            fail(ErrorKind::InvalidRequest(format!(
+                "failed to decode the {} body: {}",
+                encoding, err
+            )))
+        })?;
+        let size = state
+            .decoded
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        if size + chunk.len() as u64 > limit as u64 {
+            return Err(fail(ErrorKind::RequestTooLarge(limit)));
+        }
+        Ok(chunk)
+    });
+    let resp = next
+        .run(Request::from_parts(parts, Body::from_stream(decoded)))
+        .await;
+    match sizes.failure.lock().unwrap().take() {
+        Some(kind) => ApiError::from(kind)
+            .with_request_id(request_id.as_deref())
+            .into_response(),
+        None => resp,
+    }
+}
+
+/// Decodes base64 incrementally, ignoring line breaks and other whitespace.
+#[derive(Default)]
+pub struct Base64Decoder {
+    pending: Vec<u8>,
+}
+
+impl Base64Decoder {
+    /// Decodes all complete groups of four characters received so far.
+    pub fn update(&mut self, chunk: &[u8]) -> Result<Vec<u8>, base64::DecodeError> {
+        self.pending
+            .extend(chunk.iter().filter(|b| !b.is_ascii_whitespace()));
+        let complete = self.pending.len() / 4 * 4;
+        let decoded = BASE64.decode(&self.pending[..complete])?;
+        self.pending.drain(..complete);
+        Ok(decoded)
+    }
+
+    /// Decodes the remaining characters of unpadded input.
+    pub fn finish(self) -> Result<Vec<u8>, base64::DecodeError> {
+        BASE64.decode(&self.pending)
+    }
+}
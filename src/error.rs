@@ -0,0 +1,277 @@
+use axum::{
+    Json,
+    extract::{
+        multipart::{Field, MultipartError, MultipartRejection},
+        rejection::JsonRejection,
+    },
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::controller::JSON_REQUEST_LIMIT;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Multipart(MultipartError),
+    NotMultipart(MultipartRejection),
+    UnknownPolicy(String),
+    InvalidHashes(String),
+    FileTooLarge(usize),
+    RequestTooLarge(usize),
+    TooManyFiles(usize),
+    TooManyUploads(usize),
+    Io(std::io::Error),
+    Engine(String),
+    EngineUnavailable(Option<String>),
+    Upstream(String),
+    NotFound(String),
+    InvalidRequest(String),
+    UrlNotAllowed(String),
+    BucketNotAllowed(String),
+    OffsetMismatch(u64),
+    UnsupportedEncoding(String),
+    UnboundedEncoding(&'static str),
+}
+
+impl ErrorKind {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Multipart(err) => err.status(),
+            Self::NotMultipart(err) => err.status(),
+            Self::UnknownPolicy(_) | Self::InvalidHashes(_) | Self::InvalidRequest(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::FileTooLarge(_) | Self::RequestTooLarge(_) | Self::TooManyFiles(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            Self::Io(_) | Self::Engine(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::EngineUnavailable(_) | Self::TooManyUploads(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::UrlNotAllowed(_) | Self::BucketNotAllowed(_) => StatusCode::FORBIDDEN,
+            Self::OffsetMismatch(_) => StatusCode::CONFLICT,
+            Self::UnsupportedEncoding(_) | Self::UnboundedEncoding(_) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Multipart(_) | Self::NotMultipart(_) => "invalid-multipart",
+            Self::UnknownPolicy(_) => "unknown-policy",
+            Self::InvalidHashes(_) => "invalid-hashes",
+            Self::FileTooLarge(_) => "file-too-large",
+            Self::RequestTooLarge(_) => "request-too-large",
+            Self::TooManyFiles(_) => "too-many-files",
+            Self::TooManyUploads(_) => "too-many-uploads",
+            Self::Io(_) => "io-error",
+            Self::Engine(_) => "engine-error",
+            Self::EngineUnavailable(_) => "engine-unavailable",
+            Self::Upstream(_) => "upstream-error",
+            Self::NotFound(_) => "not-found",
+            Self::InvalidRequest(_) => "invalid-request",
+            Self::UrlNotAllowed(_) => "url-not-allowed",
+            Self::BucketNotAllowed(_) => "bucket-not-allowed",
+            Self::OffsetMismatch(_) => "offset-mismatch",
+            Self::UnsupportedEncoding(_) | Self::UnboundedEncoding(_) => "unsupported-encoding",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Multipart(_) | Self::NotMultipart(_) => "Invalid multipart request",
+            Self::UnknownPolicy(_) => "Unknown policy",
+            Self::InvalidHashes(_) => "Invalid hash algorithm list",
+            Self::FileTooLarge(_) => "File too large",
+            Self::RequestTooLarge(_) => "Request too large",
+            Self::TooManyFiles(_) => "Too many files",
+            Self::TooManyUploads(_) => "Too many uploads",
+            Self::Io(_) => "I/O error",
+            Self::Engine(_) => "Scan engine error",
+            Self::EngineUnavailable(_) => "Scan engine unavailable",
+            Self::Upstream(_) => "Upstream request failed",
+            Self::NotFound(_) => "Not found",
+            Self::InvalidRequest(_) => "Invalid request",
+            Self::UrlNotAllowed(_) => "URL not allowed",
+            Self::BucketNotAllowed(_) => "Bucket not allowed",
+            Self::OffsetMismatch(_) => "Upload offset mismatch",
+            Self::UnsupportedEncoding(_) | Self::UnboundedEncoding(_) => "Unsupported encoding",
+        }
+    }
+
+    fn limit(&self) -> Option<usize> {
+        match self {
+            Self::FileTooLarge(limit)
+            | Self::RequestTooLarge(limit)
+            | Self::TooManyFiles(limit)
+            | Self::TooManyUploads(limit) => Some(*limit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Multipart(err) => write!(f, "{}", err.body_text()),
+            Self::NotMultipart(err) => write!(f, "{}", err.body_text()),
+            Self::UnknownPolicy(name) => write!(f, "unknown policy '{}'", name),
+            Self::InvalidHashes(err) => f.write_str(err),
+            Self::FileTooLarge(limit) => write!(f, "file exceeds the limit of {} bytes", limit),
+            Self::RequestTooLarge(limit) => {
+                write!(f, "request exceeds the limit of {} bytes", limit)
+            }
+            Self::TooManyFiles(limit) => write!(f, "request exceeds the limit of {} files", limit),
+            Self::TooManyUploads(limit) => {
+                write!(f, "the limit of {} resumable uploads is reached", limit)
+            }
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Engine(err) => f.write_str(err),
+            Self::EngineUnavailable(None) => f.write_str("scan engine is not loaded yet"),
+            Self::EngineUnavailable(Some(err)) => {
+                write!(f, "scan engine is not loaded yet, last attempt: {}", err)
+            }
+            Self::Upstream(err) => write!(f, "upstream request failed: {}", err),
+            Self::NotFound(what) => write!(f, "{} not found", what),
+            Self::InvalidRequest(err) => f.write_str(err),
+            Self::UrlNotAllowed(reason) => f.write_str(reason),
+            Self::BucketNotAllowed(bucket) => write!(f, "bucket '{}' is not allowed", bucket),
+            Self::OffsetMismatch(offset) => write!(f, "expected Upload-Offset {}", offset),
+            Self::UnsupportedEncoding(encoding) => {
+                write!(
+                    f,
+                    "unsupported encoding '{}', expected gzip, br or zstd",
+                    encoding
+                )
+            }
+            Self::UnboundedEncoding(encoding) => write!(
+                f,
+                "decoding {} requires a max_file_size or max_request_size",
+                encoding
+            ),
+        }
+    }
+}
+
+/// Controller error rendered as RFC 9457 `application/problem+json`.
+#[derive(Debug)]
+pub struct ApiError {
+    kind: ErrorKind,
+    part: Option<(usize, Option<String>)>,
+    request_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'static str,
+    #[serde(rename = "partIndex", skip_serializing_if = "Option::is_none")]
+    part_index: Option<usize>,
+    #[serde(rename = "partName", skip_serializing_if = "Option::is_none")]
+    part_name: Option<&'a str>,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+}
+
+impl ApiError {
+    pub fn engine(err: impl fmt::Display) -> Self {
+        ErrorKind::Engine(err.to_string()).into()
+    }
+
+    pub fn with_part(self, index: usize, field: &Field<'_>) -> Self {
+        self.with_part_name(index, field.file_name().or(field.name()))
+    }
+
+    pub fn with_part_name(mut self, index: usize, name: Option<&str>) -> Self {
+        self.part = Some((index, name.map(|n| n.to_owned())));
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<&str>) -> Self {
+        self.request_id = request_id.map(|id| id.to_owned());
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl From<ErrorKind> for ApiError {
+    fn from(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            part: None,
+            request_id: None,
+        }
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(err: MultipartError) -> Self {
+        ErrorKind::Multipart(err).into()
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(err: MultipartRejection) -> Self {
+        ErrorKind::NotMultipart(err).into()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(err: JsonRejection) -> Self {
+        match err.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorKind::RequestTooLarge(JSON_REQUEST_LIMIT).into(),
+            _ => ErrorKind::InvalidRequest(err.body_text()).into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(err: std::io::Error) -> Self {
+        ErrorKind::Io(err).into()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.kind.status();
+        if status.is_server_error() {
+            tracing::error!("{}: {}", self.kind.code(), self.kind);
+        }
+        let problem = Problem {
+            problem_type: format!(
+                "urn:libclamav-formpost-service:problem:{}",
+                self.kind.code()
+            ),
+            title: self.kind.title(),
+            status: status.as_u16(),
+            detail: self.kind.to_string(),
+            code: self.kind.code(),
+            part_index: self.part.as_ref().map(|(index, _)| *index),
+            part_name: self.part.as_ref().and_then(|(_, name)| name.as_deref()),
+            request_id: self.request_id.as_deref(),
+            limit: self.kind.limit(),
+        };
+        let mut resp = (status, Json(problem)).into_response();
+        resp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        resp
+    }
+}
@@ -0,0 +1,355 @@
+use axum::{
+    Extension, Json,
+    extract::{ConnectInfo, rejection::JsonRejection},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use reqwest::{Url, redirect};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+use utoipa::ToSchema;
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    av::AvState,
+    controller::{self, AvResponse, PartInfo, ScanRequest, Spool, UploadQuery},
+    error::{ApiError, ErrorKind, Problem},
+    hashes::{HashAlgorithm, Hashes},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    pub schemes: Vec<String>,
+    /// Host names, or `*.example.com` for all subdomains. Empty allows any.
+    pub hosts: Vec<String>,
+    pub allow_private: bool,
+    pub max_redirects: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            schemes: vec!["https".to_owned()],
+            hosts: Vec::new(),
+            allow_private: false,
+            max_redirects: 5,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct FetchRequest {
+    url: String,
+}
+
+/// Where the scanned content was fetched from.
+#[derive(Serialize, ToSchema)]
+pub struct Origin {
+    url: String,
+    status: u16,
+    headers: BTreeMap<String, String>,
+}
+
+struct Download {
+    tmp: tempfile::NamedTempFile,
+    size: u64,
+    hashes: Hashes,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    origin: Origin,
+}
+
+/// Fetches a URL server-side and scans the content like `/upload`. Every
+/// hop of a redirect is checked against the allowed schemes and hosts and,
+/// unless `allow_private` is set, must not resolve to a private, loopback or
+/// otherwise non-public address. Responds with `404` if `[fetch]` is not
+/// configured.
+#[utoipa::path(
+    post,
+    path = "/scan/url",
+    tag = "scan",
+    params(UploadQuery),
+    request_body = FetchRequest,
+    responses(
+        (status = 200, description = "The result with its `origin`", body = AvResponse),
+        (status = "4XX", description = "Invalid request or limit exceeded", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Scan engine or upstream failure", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn scan_url(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    query: UploadQuery,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    headers: HeaderMap,
+    req: Result<Json<FetchRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Some(fetch) = &cfg.fetch else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let client = controller::audit_client(&headers, connect_info);
+    let Json(req) =
+        req.map_err(|err| ApiError::from(err).with_request_id(client.request_id.as_deref()))?;
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => {
+            let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+            let scan = ScanRequest {
+                cfg: &cfg,
+                ctx,
+                query: &query,
+                client: &client,
+                alerts,
+            };
+            fetch_and_scan(scan, fetch, &req.url).await
+        }
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    result
+        .map(|response| Json(response).into_response())
+        .map_err(|err| err.with_request_id(client.request_id.as_deref()))
+}
+
+async fn fetch_and_scan(
+    scan: ScanRequest<'_>,
+    fetch: &FetchConfig,
+    url: &str,
+) -> Result<AvResponse, ApiError> {
+    let options = scan.query.options(scan.cfg)?;
+    let url = Url::parse(url)
+        .map_err(|err| ErrorKind::InvalidRequest(format!("invalid URL '{}': {}", url, err)))?;
+    let timeout = Duration::from_secs(fetch.timeout_secs);
+    let download = download(fetch, url, scan.cfg.max_file_size, &options.algorithms);
+    let download = tokio::time::timeout(timeout, download)
+        .await
+        .map_err(|_| {
+            ErrorKind::Upstream(format!(
+                "fetch timed out after {} seconds",
+                fetch.timeout_secs
+            ))
+        })??;
+    let started = Instant::now();
+    let part = PartInfo {
+        name: None,
+        file_name: download.file_name.as_deref(),
+        content_type: download.content_type.as_deref(),
+    };
+    let path = download.tmp.path();
+    let mut result = controller::scan(
+        scan.ctx,
+        part,
+        path,
+        download.size,
+        download.hashes,
+        &options,
+    )
+    .await?;
+    controller::report(
+        &result,
+        scan.ctx,
+        scan.client,
+        scan.alerts,
+        started.elapsed(),
+    )
+    .await;
+    result.set_origin(download.origin);
+    Ok(AvResponse::new(scan.ctx, vec![result]))
+}
+
+async fn download(
+    fetch: &FetchConfig,
+    mut url: Url,
+    max_file_size: usize,
+    algorithms: &[HashAlgorithm],
+) -> Result<Download, ApiError> {
+    let upstream = |err: reqwest::Error| ErrorKind::Upstream(err.to_string());
+    let mut redirects = 0;
+    let resp = loop {
+        let addrs = resolve(fetch, &url).await?;
+        // Pins the connection to the checked addresses, so that a second DNS
+        // lookup cannot return a different one.
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .no_proxy()
+            .resolve_to_addrs(url.host_str().unwrap_or_default(), &addrs)
+            .build()
+            .map_err(upstream)?;
+        let resp = client.get(url.clone()).send().await.map_err(upstream)?;
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|v| v.to_str().ok());
+        match location {
+            Some(location) if resp.status().is_redirection() => {
+                if redirects == fetch.max_redirects {
+                    let err = format!("more than {} redirects", fetch.max_redirects);
+                    return Err(ErrorKind::Upstream(err).into());
+                }
+                redirects += 1;
+                url = url.join(location).map_err(|err| {
+                    ErrorKind::Upstream(format!("invalid redirect '{}': {}", location, err))
+                })?;
+            }
+            _ => break resp,
+        }
+    };
+    let status = resp.status();
+    if !status.is_success() {
+        let err = format!("{} responded with {}", url, status);
+        return Err(ErrorKind::Upstream(err).into());
+    }
+    if resp
+        .content_length()
+        .is_some_and(|len| len > max_file_size as u64)
+    {
+        return Err(ErrorKind::FileTooLarge(max_file_size).into());
+    }
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in resp.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers
+            .entry(name.as_str().to_owned())
+            .and_modify(|v| {
+                v.push_str(", ");
+                v.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    let content_type = headers.get(header::CONTENT_TYPE.as_str()).cloned();
+    // The last path segment, used for the policy and type checks.
+    let file_name = resp
+        .url()
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(|s| s.to_owned());
+    let origin = Origin {
+        url: resp.url().to_string(),
+        status: status.as_u16(),
+        headers,
+    };
+    let mut spool = Spool::new(algorithms)?;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(upstream)?;
+        if spool.size() + chunk.len() as u64 > max_file_size as u64 {
+            return Err(ErrorKind::FileTooLarge(max_file_size).into());
+        }
+        spool.write(&chunk)?;
+    }
+    let (tmp, size, hashes) = spool.finish()?;
+    Ok(Download {
+        tmp,
+        size,
+        hashes,
+        file_name,
+        content_type,
+        origin,
+    })
+}
+
+/// Checks the scheme and host of `url` and resolves it to the addresses
+/// which may be connected to.
+async fn resolve(fetch: &FetchConfig, url: &Url) -> Result<Vec<SocketAddr>, ApiError> {
+    let not_allowed = |reason: String| ApiError::from(ErrorKind::UrlNotAllowed(reason));
+    if !fetch.schemes.iter().any(|s| s == url.scheme()) {
+        return Err(not_allowed(format!(
+            "scheme '{}' is not allowed",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| ErrorKind::InvalidRequest(format!("URL '{}' has no host", url)))?;
+    if !fetch.hosts.is_empty() && !fetch.hosts.iter().any(|h| host_matches(h, host)) {
+        return Err(not_allowed(format!("host '{}' is not allowed", host)));
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let name = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs = tokio::net::lookup_host((name, port))
+        .await
+        .map_err(|err| ErrorKind::Upstream(format!("failed to resolve {}: {}", host, err)))?
+        .collect::<Vec<_>>();
+    if !fetch.allow_private
+        && let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip()))
+    {
+        return Err(not_allowed(format!(
+            "host '{}' resolves to the non-public address {}",
+            host,
+            addr.ip()
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Whether the host matches a `hosts` entry, which may start with
+/// `*.` to match any subdomain. Host names are compared case-insensitively.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let (pattern, host) = (pattern.to_ascii_lowercase(), host.to_ascii_lowercase());
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// Whether the address is globally routable, see the IANA special-purpose
+/// address registries.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// The IPv4 address of IPv4-mapped, IPv4-compatible, NAT64 (`64:ff9b::/96`)
+/// and 6to4 (`2002::/16`) addresses, which are routed to that address.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(v4(high, low)),
+        [0x2002, high, low, ..] => Some(v4(high, low)),
+        _ => ip.to_ipv4(),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240
+        || (a == 100 && (b & 0xc0) == 64)
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (b & 0xfe) == 18))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
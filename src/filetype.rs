@@ -0,0 +1,249 @@
+use serde::Serialize;
+use std::io::SeekFrom;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use utoipa::ToSchema;
+
+const HEAD_LIMIT: u64 = 8192;
+const TAIL_LIMIT: u64 = 65536;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Detector {
+    Infer,
+    Ooxml,
+    Libclamav,
+    Text,
+}
+
+#[derive(Clone, Copy)]
+pub struct FileType {
+    pub mime_type: &'static str,
+    pub description: &'static str,
+    pub extension: Option<&'static str>,
+    pub detector: Detector,
+}
+
+impl FileType {
+    /// Text is identified heuristically rather than by a signature, and
+    /// generic types have no extension of their own, so the declared type
+    /// and extension of such files cannot be verified.
+    pub fn is_conclusive(&self) -> bool {
+        self.detector != Detector::Text && self.extension.is_some()
+    }
+}
+
+/// Identifies the file type by trying the detectors from the most to the least
+/// specific: magic numbers via `infer` (refined for OOXML containers), the
+/// type libclamav assigned to the root layer, and finally text encodings.
+#[tracing::instrument(name = "detect_type", skip(path))]
+pub async fn detect(
+    path: &str,
+    clamav_type: Option<&str>,
+) -> Result<Option<FileType>, std::io::Error> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let mut head = Vec::with_capacity(std::cmp::min(HEAD_LIMIT, size) as usize);
+    (&mut file).take(HEAD_LIMIT).read_to_end(&mut head).await?;
+    if let Some(t) = infer::get(&head) {
+        if t.mime_type() == "application/zip" {
+            let mut tail = Vec::with_capacity(std::cmp::min(TAIL_LIMIT, size) as usize);
+            file.seek(SeekFrom::Start(size.saturating_sub(TAIL_LIMIT)))
+                .await?;
+            file.take(TAIL_LIMIT).read_to_end(&mut tail).await?;
+            if let Some(ooxml) = detect_ooxml(&head, &tail) {
+                return Ok(Some(ooxml));
+            }
+        }
+        return Ok(Some(FileType {
+            mime_type: t.mime_type(),
+            description: describe_matcher(t.matcher_type()),
+            extension: Some(t.extension()),
+            detector: Detector::Infer,
+        }));
+    }
+    if let Some(t) = clamav_type.and_then(from_clamav_type) {
+        return Ok(Some(t));
+    }
+    Ok(detect_text(&head, size > HEAD_LIMIT))
+}
+
+fn describe_matcher(matcher: infer::MatcherType) -> &'static str {
+    match matcher {
+        infer::MatcherType::App => "Application",
+        infer::MatcherType::Archive => "Archive",
+        infer::MatcherType::Audio => "Audio",
+        infer::MatcherType::Book => "E-book",
+        infer::MatcherType::Doc => "Document",
+        infer::MatcherType::Font => "Font",
+        infer::MatcherType::Image => "Image",
+        infer::MatcherType::Text => "Text",
+        infer::MatcherType::Video => "Video",
+        infer::MatcherType::Custom => "Custom",
+    }
+}
+
+/// Looks for the OOXML part names in the local headers at the start and in the
+/// central directory at the end of a ZIP file.
+fn detect_ooxml(head: &[u8], tail: &[u8]) -> Option<FileType> {
+    let contains = |needle: &[u8]| {
+        head.windows(needle.len()).any(|w| w == needle)
+            || tail.windows(needle.len()).any(|w| w == needle)
+    };
+    if !contains(b"[Content_Types].xml") {
+        return None;
+    }
+    let (mime_type, description, extension) = if contains(b"word/") {
+        (
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "Office Open XML document",
+            "docx",
+        )
+    } else if contains(b"xl/") {
+        (
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "Office Open XML spreadsheet",
+            "xlsx",
+        )
+    } else if contains(b"ppt/") {
+        (
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "Office Open XML presentation",
+            "pptx",
+        )
+    } else {
+        return None;
+    };
+    Some(FileType {
+        mime_type,
+        description,
+        extension: Some(extension),
+        detector: Detector::Ooxml,
+    })
+}
+
+/// Maps the libclamav type of the root layer. Types which cover several
+/// formats, like scripts of any language, are reported without an extension.
+fn from_clamav_type(clamav_type: &str) -> Option<FileType> {
+    let generic = match clamav_type {
+        "CL_TYPE_SCRIPT" => Some(("text/plain", "Script")),
+        "CL_TYPE_GRAPHICS" => Some(("application/octet-stream", "Image")),
+        "CL_TYPE_ELF" => Some(("application/x-executable", "ELF executable or library")),
+        _ => None,
+    };
+    if let Some((mime_type, description)) = generic {
+        return Some(FileType {
+            mime_type,
+            description,
+            extension: None,
+            detector: Detector::Libclamav,
+        });
+    }
+    let (mime_type, description, extension) = match clamav_type {
+        "CL_TYPE_MSEXE" => ("application/x-msdownload", "Windows PE executable", "exe"),
+        "CL_TYPE_MACHO" | "CL_TYPE_MACHO_UNIBIN" => {
+            ("application/x-mach-binary", "Mach-O executable", "macho")
+        }
+        "CL_TYPE_MSOLE2" => ("application/x-ole-storage", "OLE2 compound document", "ole"),
+        "CL_TYPE_OOXML_WORD" => (
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "Office Open XML document",
+            "docx",
+        ),
+        "CL_TYPE_OOXML_XL" => (
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "Office Open XML spreadsheet",
+            "xlsx",
+        ),
+        "CL_TYPE_OOXML_PPT" => (
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "Office Open XML presentation",
+            "pptx",
+        ),
+        "CL_TYPE_PDF" => ("application/pdf", "PDF document", "pdf"),
+        "CL_TYPE_RTF" => ("application/rtf", "Rich Text Format document", "rtf"),
+        "CL_TYPE_HTML" | "CL_TYPE_HTML_UTF16" => ("text/html", "HTML document", "html"),
+        "CL_TYPE_MAIL" => ("message/rfc822", "E-mail message", "eml"),
+        "CL_TYPE_XML_WORD" => ("application/xml", "Word 2003 XML document", "xml"),
+        "CL_TYPE_XML_XL" => ("application/xml", "Excel 2003 XML spreadsheet", "xml"),
+        "CL_TYPE_HWP3" | "CL_TYPE_HWPOLE2" => ("application/x-hwp", "Hangul document", "hwp"),
+        "CL_TYPE_ZIP" | "CL_TYPE_ZIPSFX" => ("application/zip", "ZIP archive", "zip"),
+        "CL_TYPE_RAR" | "CL_TYPE_RARSFX" => ("application/vnd.rar", "RAR archive", "rar"),
+        "CL_TYPE_7Z" | "CL_TYPE_7ZSFX" => ("application/x-7z-compressed", "7-Zip archive", "7z"),
+        "CL_TYPE_GZ" => ("application/gzip", "Gzip archive", "gz"),
+        "CL_TYPE_BZ" => ("application/x-bzip2", "Bzip2 archive", "bz2"),
+        "CL_TYPE_XZ" => ("application/x-xz", "XZ archive", "xz"),
+        "CL_TYPE_CPIO_OLD" | "CL_TYPE_CPIO_ODC" | "CL_TYPE_CPIO_NEWC" | "CL_TYPE_CPIO_CRC" => {
+            ("application/x-cpio", "cpio archive", "cpio")
+        }
+        "CL_TYPE_POSIX_TAR" | "CL_TYPE_OLD_TAR" => ("application/x-tar", "Tar archive", "tar"),
+        "CL_TYPE_CAB" => (
+            "application/vnd.ms-cab-compressed",
+            "Cabinet archive",
+            "cab",
+        ),
+        "CL_TYPE_CHM" => ("application/vnd.ms-htmlhelp", "Compiled HTML Help", "chm"),
+        "CL_TYPE_ISO9660" => ("application/x-iso9660-image", "ISO 9660 image", "iso"),
+        "CL_TYPE_DMG" => ("application/x-apple-diskimage", "Apple disk image", "dmg"),
+        "CL_TYPE_SWF" => ("application/x-shockwave-flash", "Flash movie", "swf"),
+        "CL_TYPE_JAVA" => ("application/java-vm", "Java class file", "class"),
+        "CL_TYPE_PNG" => ("image/png", "PNG image", "png"),
+        "CL_TYPE_GIF" => ("image/gif", "GIF image", "gif"),
+        "CL_TYPE_JPEG" => ("image/jpeg", "JPEG image", "jpg"),
+        "CL_TYPE_TIFF" => ("image/tiff", "TIFF image", "tif"),
+        "CL_TYPE_ONENOTE" => ("application/onenote", "OneNote document", "one"),
+        _ => return None,
+    };
+    Some(FileType {
+        mime_type,
+        description,
+        extension: Some(extension),
+        detector: Detector::Libclamav,
+    })
+}
+
+/// Recognizes text by its byte order mark, or as ASCII or UTF-8 without
+/// control characters other than whitespace. A multibyte sequence cut off by
+/// the read limit is tolerated if the file is longer than the sample.
+fn detect_text(head: &[u8], truncated: bool) -> Option<FileType> {
+    if head.is_empty() {
+        return None;
+    }
+    let description = if head.starts_with(b"\xEF\xBB\xBF") {
+        "UTF-8 text with BOM"
+    } else if head.starts_with(b"\xFF\xFE\x00\x00") {
+        "UTF-32LE text"
+    } else if head.starts_with(b"\x00\x00\xFE\xFF") {
+        "UTF-32BE text"
+    } else if head.starts_with(b"\xFF\xFE") {
+        "UTF-16LE text"
+    } else if head.starts_with(b"\xFE\xFF") {
+        "UTF-16BE text"
+    } else {
+        let valid = match std::str::from_utf8(head) {
+            Ok(s) => s,
+            Err(e) if truncated && e.error_len().is_none() => {
+                std::str::from_utf8(&head[..e.valid_up_to()]).ok()?
+            }
+            Err(_) => return None,
+        };
+        if valid
+            .chars()
+            .any(|c| c.is_control() && !c.is_ascii_whitespace() && c != '\x1b')
+        {
+            return None;
+        }
+        match valid.is_ascii() {
+            true => "ASCII text",
+            false => "UTF-8 text",
+        }
+    };
+    Some(FileType {
+        mime_type: "text/plain",
+        description,
+        extension: None,
+        detector: Detector::Text,
+    })
+}
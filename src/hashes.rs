@@ -0,0 +1,163 @@
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+    Ssdeep,
+    Tlsh,
+}
+
+impl HashAlgorithm {
+    pub const DEFAULT: &[HashAlgorithm] = &[Self::Crc32, Self::Md5, Self::Sha256];
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "crc32" => Ok(Self::Crc32),
+            "md5" => Ok(Self::Md5),
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            "sha512" => Ok(Self::Sha512),
+            "blake3" => Ok(Self::Blake3),
+            "ssdeep" => Ok(Self::Ssdeep),
+            "tlsh" => Ok(Self::Tlsh),
+            other => Err(format!("unknown hash algorithm '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Crc32 => "crc32",
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+            Self::Ssdeep => "ssdeep",
+            Self::Tlsh => "tlsh",
+        })
+    }
+}
+
+/// Parses a comma separated list such as `sha1,sha256,blake3`.
+pub fn parse_list(list: &str) -> Result<Vec<HashAlgorithm>, String> {
+    list.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(HashAlgorithm::from_str)
+        .collect()
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct Hashes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crc32: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssdeep: Option<String>,
+    /// TLSH needs at least 50 bytes with some variance, otherwise it is `null`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tlsh: Option<Option<String>>,
+}
+
+/// Feeds each chunk to the selected digests only.
+#[derive(Default)]
+pub struct Hasher {
+    crc32: Option<crc_fast::Digest>,
+    md5: Option<md5::Md5>,
+    sha1: Option<sha1::Sha1>,
+    sha256: Option<sha2::Sha256>,
+    sha512: Option<sha2::Sha512>,
+    blake3: Option<blake3::Hasher>,
+    ssdeep: Option<fuzzyhash::FuzzyHash>,
+    tlsh: Option<tlsh2::TlshDefaultBuilder>,
+}
+
+impl Hasher {
+    pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+        let mut h = Self::default();
+        for a in algorithms {
+            match a {
+                HashAlgorithm::Crc32 => {
+                    h.crc32 = Some(crc_fast::Digest::new(crc_fast::CrcAlgorithm::Crc32IsoHdlc))
+                }
+                HashAlgorithm::Md5 => h.md5 = Some(md5::Md5::new()),
+                HashAlgorithm::Sha1 => h.sha1 = Some(sha1::Sha1::new()),
+                HashAlgorithm::Sha256 => h.sha256 = Some(sha2::Sha256::new()),
+                HashAlgorithm::Sha512 => h.sha512 = Some(sha2::Sha512::new()),
+                HashAlgorithm::Blake3 => h.blake3 = Some(blake3::Hasher::new()),
+                HashAlgorithm::Ssdeep => h.ssdeep = Some(fuzzyhash::FuzzyHash::default()),
+                HashAlgorithm::Tlsh => h.tlsh = Some(tlsh2::TlshDefaultBuilder::new()),
+            }
+        }
+        h
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if let Some(d) = self.crc32.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.md5.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.sha1.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.sha256.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.sha512.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.blake3.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.ssdeep.as_mut() {
+            d.update(chunk);
+        }
+        if let Some(d) = self.tlsh.as_mut() {
+            d.update(chunk);
+        }
+    }
+
+    pub fn finalize(self) -> Hashes {
+        Hashes {
+            crc32: self.crc32.map(|d| format!("{:08x?}", d.finalize())),
+            md5: self.md5.map(|d| const_hex::encode(d.finalize())),
+            sha1: self.sha1.map(|d| const_hex::encode(d.finalize())),
+            sha256: self.sha256.map(|d| const_hex::encode(d.finalize())),
+            sha512: self.sha512.map(|d| const_hex::encode(d.finalize())),
+            blake3: self.blake3.map(|d| d.finalize().to_hex().to_string()),
+            ssdeep: self.ssdeep.map(|mut d| {
+                d.finalize();
+                d.to_string()
+            }),
+            tlsh: self.tlsh.map(|d| {
+                d.build()
+                    .map(|t| String::from_utf8_lossy(&t.hash()).into_owned())
+            }),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{Extension, Json};
+use chrono::{SecondsFormat, Utc};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path, sync::Arc};
+use utoipa::ToSchema;
+
+use crate::{app_config::AppConfig, av::AvState};
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    status: &'static str,
+    engine: &'static str,
+    #[serde(rename = "engineError", skip_serializing_if = "Option::is_none")]
+    engine_error: Option<String>,
+    #[serde(rename = "dbDate")]
+    db_date: Option<String>,
+    #[serde(rename = "dbAgeSeconds")]
+    db_age_secs: Option<i64>,
+    #[serde(rename = "tmpDir")]
+    tmp_dir: String,
+    #[serde(rename = "tmpFreeBytes")]
+    tmp_free_bytes: Option<u64>,
+    #[serde(rename = "pendingUploads")]
+    pending_uploads: usize,
+    reasons: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The process is running", body = String))
+)]
+pub async fn live() -> &'static str {
+    "OK"
+}
+
+/// Reports `DOWN` with `503` until the engine is compiled, or if the database
+/// or the free temp disk space violate the configured thresholds.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready to scan", body = Readiness),
+        (status = 503, description = "Not ready, see `reasons`", body = Readiness),
+    )
+)]
+pub async fn ready(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+) -> (StatusCode, Json<Readiness>) {
+    let mut reasons = Vec::new();
+    let ctx = av.get();
+    let engine_error = match ctx {
+        Some(_) => None,
+        None => {
+            reasons.push("engine is not loaded".to_owned());
+            av.last_error()
+        }
+    };
+    let db_age_secs = ctx.map(|ctx| (Utc::now() - ctx.db_date).num_seconds());
+    if let Some((age, max)) = db_age_secs.zip(cfg.max_db_age_secs)
+        && age > max as i64
+    {
+        reasons.push(format!("database is {}s old, limit is {}s", age, max));
+    }
+    let tmp_dir = std::env::temp_dir();
+    let tmp_free_bytes = free_space(&tmp_dir);
+    match tmp_free_bytes {
+        Some(free) if free < cfg.min_tmp_free_bytes => reasons.push(format!(
+            "{} bytes free in temp dir, limit is {}",
+            free, cfg.min_tmp_free_bytes
+        )),
+        None if cfg.min_tmp_free_bytes > 0 => {
+            reasons.push("free space of temp dir is unknown".to_owned())
+        }
+        _ => {}
+    }
+    let status = match reasons.is_empty() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let readiness = Readiness {
+        status: if status.is_success() { "UP" } else { "DOWN" },
+        engine: if ctx.is_some() { "READY" } else { "LOADING" },
+        engine_error,
+        db_date: ctx.map(|ctx| ctx.db_date.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        db_age_secs,
+        tmp_dir: tmp_dir.to_string_lossy().into_owned(),
+        tmp_free_bytes,
+        pending_uploads: av.pending(),
+        reasons,
+    };
+    (status, Json(readiness))
+}
+
+fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    match unsafe { libc::statvfs(path.as_ptr(), &mut stat) } {
+        0 => Some(stat.f_bavail as u64 * stat.f_frsize as u64),
+        _ => None,
+    }
+}
@@ -0,0 +1,518 @@
+use axum::{
+    Extension, Json,
+    body::{Body, BodyDataStream, Bytes},
+    extract::ConnectInfo,
+    http::HeaderMap,
+};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+use tokio_stream::StreamExt;
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    av::AvState,
+    controller::{self, AvResponse, PartInfo, ScanRequest, Spool, UploadQuery},
+    encoding::Base64Decoder,
+    error::{ApiError, ErrorKind, Problem},
+    hashes::{HashAlgorithm, Hashes},
+    openapi::ScanJsonRequest,
+};
+
+/// Upper bound for keys, names and content types, which are buffered.
+const MAX_STRING_LEN: usize = 4096;
+
+/// Scans the files of a `{"files": [{"name", "contentType", "contentBase64"}]}`
+/// document like `/upload`. The body is parsed while it streams in and the
+/// base64 content is decoded straight into the temp file, so neither the
+/// document nor a file is held in memory. The size limits apply to the
+/// decoded content.
+#[utoipa::path(
+    post,
+    path = "/scan/json",
+    tag = "scan",
+    params(UploadQuery),
+    request_body = ScanJsonRequest,
+    responses(
+        (status = 200, description = "One result per file", body = AvResponse),
+        (status = "4XX", description = "Invalid request or limit exceeded", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Scan engine failed or not loaded yet", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn scan_json(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    query: UploadQuery,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<AvResponse>, ApiError> {
+    let client = controller::audit_client(&headers, connect_info);
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => {
+            let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+            let scan = ScanRequest {
+                cfg: &cfg,
+                ctx,
+                query: &query,
+                client: &client,
+                alerts,
+            };
+            receive(scan, body).await
+        }
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    result
+        .map(Json)
+        .map_err(|err| err.with_request_id(client.request_id.as_deref()))
+}
+
+#[derive(Default)]
+struct JsonFile {
+    name: Option<String>,
+    content_type: Option<String>,
+    content: Option<Content>,
+}
+
+struct Content {
+    tmp: tempfile::NamedTempFile,
+    size: u64,
+    hashes: Hashes,
+    encoded_size: u64,
+}
+
+async fn receive(scan: ScanRequest<'_>, body: Body) -> Result<AvResponse, ApiError> {
+    let cfg = scan.cfg;
+    let options = scan.query.options(cfg)?;
+    let mut reader = Reader::new(body);
+    let mut results = Vec::new();
+    let mut total = 0;
+    let mut files = false;
+    reader.expect(b'{').await?;
+    let mut more = reader.open(b'}').await?;
+    while more {
+        let key = reader.string().await?;
+        reader.expect(b':').await?;
+        if key != "files" {
+            reader.skip_value().await?;
+            more = reader.next_item(b'}').await?;
+            continue;
+        }
+        files = true;
+        reader.expect(b'[').await?;
+        let mut more_files = reader.open(b']').await?;
+        while more_files {
+            let index = results.len();
+            if index >= cfg.max_files_per_request {
+                let err = ApiError::from(ErrorKind::TooManyFiles(cfg.max_files_per_request));
+                return Err(err.with_part_name(index, None));
+            }
+            let mut file = JsonFile::default();
+            receive_file(cfg, &mut reader, &mut file, &mut total, &options.algorithms)
+                .await
+                .map_err(|err| err.with_part_name(index, file.name.as_deref()))?;
+            let name = file.name.as_deref();
+            let Some(content) = file.content else {
+                let err = ErrorKind::InvalidRequest(format!(
+                    "files[{}].contentBase64 is required",
+                    index
+                ));
+                return Err(ApiError::from(err).with_part_name(index, name));
+            };
+            let started = Instant::now();
+            let part = PartInfo {
+                name: None,
+                file_name: name,
+                content_type: file.content_type.as_deref(),
+            };
+            let path = content.tmp.path();
+            let mut result =
+                controller::scan(scan.ctx, part, path, content.size, content.hashes, &options)
+                    .await
+                    .map_err(|err| err.with_part_name(index, name))?;
+            result.set_encoded_size(content.encoded_size);
+            controller::report(
+                &result,
+                scan.ctx,
+                scan.client,
+                scan.alerts,
+                started.elapsed(),
+            )
+            .await;
+            results.push(result);
+            more_files = reader.next_item(b']').await?;
+        }
+        more = reader.next_item(b'}').await?;
+    }
+    reader.end().await?;
+    if !files {
+        return Err(ErrorKind::InvalidRequest("files is required".to_owned()).into());
+    }
+    Ok(AvResponse::new(scan.ctx, results))
+}
+
+/// Reads one entry of `files` into `file`, spooling its decoded content.
+/// On errors, `file` keeps what was read so far, such as the name.
+async fn receive_file(
+    cfg: &AppConfig,
+    reader: &mut Reader,
+    file: &mut JsonFile,
+    total: &mut usize,
+    algorithms: &[HashAlgorithm],
+) -> Result<(), ApiError> {
+    reader.expect(b'{').await?;
+    let mut more = reader.open(b'}').await?;
+    while more {
+        let key = reader.string().await?;
+        reader.expect(b':').await?;
+        match key.as_str() {
+            "name" => file.name = reader.optional_string().await?,
+            "contentType" => file.content_type = reader.optional_string().await?,
+            "contentBase64" if file.content.is_some() => {
+                let err = "contentBase64 must be given once per file".to_owned();
+                return Err(ErrorKind::InvalidRequest(err).into());
+            }
+            "contentBase64" => {
+                let mut spool = Spool::new(algorithms)?;
+                let mut decoder = Base64Decoder::default();
+                let encoded_size = reader
+                    .base64(|chunk| {
+                        let decoded = decoder.update(chunk).map_err(invalid_base64)?;
+                        controller::append_part(cfg, &mut spool, total, &decoded)
+                    })
+                    .await?;
+                let decoded = decoder.finish().map_err(invalid_base64)?;
+                controller::append_part(cfg, &mut spool, total, &decoded)?;
+                let (tmp, size, hashes) = spool.finish()?;
+                file.content = Some(Content {
+                    tmp,
+                    size,
+                    hashes,
+                    encoded_size,
+                });
+            }
+            _ => reader.skip_value().await?,
+        }
+        more = reader.next_item(b'}').await?;
+    }
+    Ok(())
+}
+
+fn invalid_base64(err: base64::DecodeError) -> ApiError {
+    ErrorKind::InvalidRequest(format!("invalid base64 content: {}", err)).into()
+}
+
+/// Pull parser over the request body, just enough JSON for the document
+/// above. Values of unknown keys are skipped without being buffered.
+struct Reader {
+    stream: BodyDataStream,
+    chunk: Bytes,
+    pos: usize,
+    /// Bytes consumed before `chunk`, for error messages.
+    offset: u64,
+}
+
+impl Reader {
+    fn new(body: Body) -> Self {
+        Self {
+            stream: body.into_data_stream(),
+            chunk: Bytes::new(),
+            pos: 0,
+            offset: 0,
+        }
+    }
+
+    fn syntax(&self, expected: &str) -> ApiError {
+        let err = format!(
+            "invalid JSON at byte {}: expected {}",
+            self.offset + self.pos as u64,
+            expected
+        );
+        ErrorKind::InvalidRequest(err).into()
+    }
+
+    async fn peek(&mut self) -> Result<Option<u8>, ApiError> {
+        while self.pos == self.chunk.len() {
+            match self.stream.next().await {
+                Some(chunk) => {
+                    let chunk = chunk.map_err(|err| {
+                        ErrorKind::InvalidRequest(format!("failed to read body: {}", err))
+                    })?;
+                    self.offset += self.chunk.len() as u64;
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.chunk[self.pos]))
+    }
+
+    async fn next(&mut self, expected: &str) -> Result<u8, ApiError> {
+        let byte = self.peek().await?.ok_or_else(|| self.syntax(expected))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    async fn skip_ws(&mut self) -> Result<Option<u8>, ApiError> {
+        while let Some(byte) = self.peek().await? {
+            if !matches!(byte, b' ' | b'\t' | b'\n' | b'\r') {
+                return Ok(Some(byte));
+            }
+            self.pos += 1;
+        }
+        Ok(None)
+    }
+
+    async fn expect(&mut self, byte: u8) -> Result<(), ApiError> {
+        let expected = format!("'{}'", byte as char);
+        match self.skip_ws().await? {
+            Some(b) if b == byte => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.syntax(&expected)),
+        }
+    }
+
+    /// After an opening bracket, whether the object or array has entries.
+    async fn open(&mut self, close: u8) -> Result<bool, ApiError> {
+        if self.skip_ws().await? == Some(close) {
+            self.pos += 1;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// After an entry, whether another one follows.
+    async fn next_item(&mut self, close: u8) -> Result<bool, ApiError> {
+        let expected = format!("',' or '{}'", close as char);
+        match self.skip_ws().await? {
+            Some(b',') => {
+                self.pos += 1;
+                Ok(true)
+            }
+            Some(b) if b == close => {
+                self.pos += 1;
+                Ok(false)
+            }
+            _ => Err(self.syntax(&expected)),
+        }
+    }
+
+    /// Checks that nothing but whitespace follows the document.
+    async fn end(&mut self) -> Result<(), ApiError> {
+        match self.skip_ws().await? {
+            None => Ok(()),
+            Some(_) => Err(self.syntax("end of input")),
+        }
+    }
+
+    async fn string(&mut self) -> Result<String, ApiError> {
+        self.expect(b'"').await?;
+        let mut buf = Vec::new();
+        loop {
+            if buf.len() > MAX_STRING_LEN {
+                let err = format!("string exceeds {} bytes", MAX_STRING_LEN);
+                return Err(ErrorKind::InvalidRequest(err).into());
+            }
+            match self.next("'\"'").await? {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.next("escape").await? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape().await?,
+                        _ => return Err(self.syntax("escape")),
+                    };
+                    buf.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte if byte < 0x20 => return Err(self.syntax("'\"'")),
+                byte => buf.push(byte),
+            }
+        }
+        String::from_utf8(buf).map_err(|_| self.syntax("UTF-8"))
+    }
+
+    async fn optional_string(&mut self) -> Result<Option<String>, ApiError> {
+        if self.skip_ws().await? == Some(b'n') {
+            self.literal(b"null").await?;
+            return Ok(None);
+        }
+        self.string().await.map(Some)
+    }
+
+    /// Reads the digits of a `\u` escape, combining surrogate pairs.
+    async fn unicode_escape(&mut self) -> Result<char, ApiError> {
+        let high = self.hex4().await?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.syntax("unicode escape"));
+        }
+        self.literal(b"\\u").await?;
+        let low = self.hex4().await?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.syntax("low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.syntax("unicode escape"))
+    }
+
+    async fn hex4(&mut self) -> Result<u32, ApiError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let byte = self.next("hex digit").await?;
+            let digit = (byte as char)
+                .to_digit(16)
+                .ok_or_else(|| self.syntax("hex digit"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    async fn literal(&mut self, literal: &[u8]) -> Result<(), ApiError> {
+        let expected = String::from_utf8_lossy(literal).into_owned();
+        for byte in literal {
+            if self.next(&expected).await? != *byte {
+                return Err(self.syntax(&expected));
+            }
+        }
+        Ok(())
+    }
+
+    /// Streams a base64 string to `sink` in slices as received, returning
+    /// its encoded length. Escaped `/` and line breaks are allowed.
+    async fn base64(
+        &mut self,
+        mut sink: impl FnMut(&[u8]) -> Result<(), ApiError>,
+    ) -> Result<u64, ApiError> {
+        self.expect(b'"').await?;
+        let mut size = 0;
+        loop {
+            if self.peek().await?.is_none() {
+                return Err(self.syntax("'\"'"));
+            }
+            let rest = &self.chunk[self.pos..];
+            let len = rest
+                .iter()
+                .position(|b| *b == b'"' || *b == b'\\')
+                .unwrap_or(rest.len());
+            if len > 0 {
+                let slice = self.chunk.slice(self.pos..self.pos + len);
+                self.pos += len;
+                size += len as u64;
+                sink(&slice)?;
+                continue;
+            }
+            if self.next("'\"'").await? == b'"' {
+                return Ok(size);
+            }
+            match self.next("escape").await? {
+                b'/' => sink(b"/")?,
+                b'n' | b'r' | b't' => {}
+                _ => return Err(self.syntax("base64 character")),
+            }
+            size += 2;
+        }
+    }
+
+    /// Skips a value of any type, nesting without recursion. The closing
+    /// brackets of the open objects and arrays are kept on a stack, so that
+    /// the value is checked like any other part of the document.
+    async fn skip_value(&mut self) -> Result<(), ApiError> {
+        let mut stack = Vec::new();
+        loop {
+            match self.skip_ws().await? {
+                Some(b'{') => {
+                    self.pos += 1;
+                    if self.open(b'}').await? {
+                        stack.push(b'}');
+                        self.skip_string().await?;
+                        self.expect(b':').await?;
+                        continue;
+                    }
+                }
+                Some(b'[') => {
+                    self.pos += 1;
+                    if self.open(b']').await? {
+                        stack.push(b']');
+                        continue;
+                    }
+                }
+                Some(b'"') => self.skip_string().await?,
+                Some(b't') => self.literal(b"true").await?,
+                Some(b'f') => self.literal(b"false").await?,
+                Some(b'n') => self.literal(b"null").await?,
+                Some(b'-' | b'0'..=b'9') => self.skip_number().await?,
+                _ => return Err(self.syntax("value")),
+            }
+            // A value is complete, close the brackets until an entry follows.
+            loop {
+                let Some(&close) = stack.last() else {
+                    return Ok(());
+                };
+                if self.next_item(close).await? {
+                    if close == b'}' {
+                        self.skip_string().await?;
+                        self.expect(b':').await?;
+                    }
+                    break;
+                }
+                stack.pop();
+            }
+        }
+    }
+
+    async fn skip_number(&mut self) -> Result<(), ApiError> {
+        if self.peek().await? == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek().await? {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.skip_digits().await?,
+            _ => return Err(self.syntax("digit")),
+        }
+        if self.peek().await? == Some(b'.') {
+            self.pos += 1;
+            self.skip_digits().await?;
+        }
+        if matches!(self.peek().await?, Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek().await?, Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.skip_digits().await?;
+        }
+        Ok(())
+    }
+
+    /// Skips one or more digits.
+    async fn skip_digits(&mut self) -> Result<(), ApiError> {
+        if !self.peek().await?.is_some_and(|b| b.is_ascii_digit()) {
+            return Err(self.syntax("digit"));
+        }
+        while self.peek().await?.is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    async fn skip_string(&mut self) -> Result<(), ApiError> {
+        self.expect(b'"').await?;
+        loop {
+            match self.next("'\"'").await? {
+                b'"' => return Ok(()),
+                b'\\' => {
+                    self.next("escape").await?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use axum::{
+    Json,
+    extract::Path,
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use std::sync::Arc;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::Config;
+
+use crate::{controller, fetch, health, json_upload, s3, tus};
+
+const DOCS_HTML: &[u8] = include_bytes!("docs.html");
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        controller::upload,
+        json_upload::scan_json,
+        fetch::scan_url,
+        s3::scan_object,
+        tus::status,
+        health::live,
+        health::ready,
+    ),
+    tags(
+        (name = "scan", description = "Scan files and report the verdicts"),
+        (name = "tus", description = "Results of resumable uploads"),
+        (name = "health", description = "Liveness and readiness probes"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Form of `/upload`, any number of file parts regardless of their names.
+/// Like the types below, it only documents a body which the handler parses
+/// while streaming.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ScanJsonRequest {
+    files: Vec<ScanJsonFile>,
+}
+
+#[derive(ToSchema)]
+#[schema(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct ScanJsonFile {
+    name: Option<String>,
+    content_type: Option<String>,
+    /// The file content, line breaks are ignored.
+    #[schema(format = Byte)]
+    content_base64: String,
+}
+
+/// The OpenAPI 3.1 document of the JSON endpoints.
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub async fn docs_html() -> Html<&'static [u8]> {
+    Html(DOCS_HTML)
+}
+
+/// Files of the Swagger UI bundled into the binary, so that `/docs` works
+/// without access to a CDN.
+pub async fn docs_asset(Path(file): Path<String>) -> Response {
+    match utoipa_swagger_ui::serve(&file, Arc::new(Config::from("openapi.json"))) {
+        Ok(Some(file)) => (
+            [(header::CONTENT_TYPE, file.content_type)],
+            file.bytes.into_owned(),
+        )
+            .into_response(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
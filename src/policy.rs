@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub content_types: Vec<String>,
+    pub extensions: Vec<String>,
+    #[serde(deserialize_with = "crate::app_config::deserialize_opt_size")]
+    pub max_file_size: Option<u64>,
+    pub allow_macros: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            content_types: Vec::new(),
+            extensions: Vec::new(),
+            max_file_size: None,
+            allow_macros: true,
+        }
+    }
+}
+
+/// Signature prefix reported by libclamav when macro blocking is enabled.
+pub const MACROS_SIGNATURE: &str = "Heuristics.OLE2.ContainsMacros";
+
+impl Policy {
+    /// Returns the reason for rejecting a file, or `None` if it conforms.
+    ///
+    /// Empty allowlists are not enforced. A declared content type is only
+    /// checked if the client sent one.
+    pub fn check(
+        &self,
+        file_name: Option<&str>,
+        declared_type: Option<&str>,
+        detected_type: Option<&str>,
+        size: u64,
+    ) -> Option<String> {
+        if let Some(max) = self.max_file_size.filter(|max| size > *max) {
+            return Some(format!("file size {} exceeds limit of {}", size, max));
+        }
+        if !self.extensions.is_empty() {
+            let ext = file_name
+                .and_then(|n| n.rsplit_once('.'))
+                .map(|(_, ext)| ext.to_ascii_lowercase());
+            match ext {
+                Some(ext) if self.extensions.iter().any(|e| e.eq_ignore_ascii_case(&ext)) => {}
+                Some(ext) => return Some(format!("file extension '{}' is not allowed", ext)),
+                None => return Some("file extension is missing".to_owned()),
+            }
+        }
+        if !self.content_types.is_empty() {
+            match detected_type {
+                Some(t) if self.allows_type(t) => {}
+                Some(t) => return Some(format!("detected content type '{}' is not allowed", t)),
+                None => return Some("content type could not be detected".to_owned()),
+            }
+            if let Some(t) = declared_type.filter(|t| !self.allows_type(t)) {
+                return Some(format!("declared content type '{}' is not allowed", t));
+            }
+        }
+        None
+    }
+
+    /// Matches a MIME type against the allowlist, supporting `type/*` entries.
+    fn allows_type(&self, mime: &str) -> bool {
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(prefix) => mime
+                    .split_once('/')
+                    .is_some_and(|(t, _)| t.eq_ignore_ascii_case(prefix)),
+                None => allowed.eq_ignore_ascii_case(mime),
+            })
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{ConnectInfo, FromRequest, Multipart, Request},
+    http::{HeaderMap, HeaderName, header},
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use std::{io::Write, net::SocketAddr, sync::Arc};
+use tokio::{select, sync::mpsc};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    av::{AvContext, AvState},
+    controller::{self, AvResponse, ScanRequest, UploadQuery},
+    error::{ApiError, ErrorKind},
+};
+
+/// Headers describing the verdict, added to the forwarded request. Clients
+/// cannot set them, since any incoming `x-scan-*` header is dropped.
+const SCAN_HEADER_PREFIX: &str = "x-scan-";
+
+/// Scans a multipart request like `/upload` and only if all parts are clean
+/// forwards the original request to `proxy_upstream`, returning the upstream
+/// response. Otherwise responds with `422` and the `AvResponse`.
+pub async fn forward(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    Extension(http): Extension<reqwest::Client>,
+    query: UploadQuery,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    req: Request,
+) -> Result<Response, ApiError> {
+    let Some(upstream) = &cfg.proxy_upstream else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let client = controller::audit_client(req.headers(), connect_info);
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => {
+            let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+            let scan = ScanRequest {
+                cfg: &cfg,
+                ctx,
+                query: &query,
+                client: &client,
+                alerts,
+            };
+            relay(scan, &http, upstream, req).await
+        }
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    result.map_err(|err| err.with_request_id(client.request_id.as_deref()))
+}
+
+async fn relay(
+    scan: ScanRequest<'_>,
+    http: &reqwest::Client,
+    upstream: &str,
+    req: Request,
+) -> Result<Response, ApiError> {
+    let (parts, body) = req.into_parts();
+    let headers = parts.headers.clone();
+    let url = match parts.uri.query() {
+        Some(query) => format!("{}?{}", upstream, query),
+        None => upstream.to_owned(),
+    };
+    // Tees the raw body into a temp file while the parts are being scanned.
+    // The whole body is written, also what follows the closing boundary and
+    // is never read by the multipart parser.
+    let raw = tempfile::Builder::new().rand_bytes(12).tempfile()?;
+    let mut writer = raw.reopen()?;
+    let (tx, rx) = mpsc::channel(1);
+    let tee = async move {
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    writer.write_all(&chunk)?;
+                    // Fails once the parser is done, the rest is only written.
+                    let _ = tx.send(Ok(chunk)).await;
+                }
+                Err(err) => {
+                    let msg = err.to_string();
+                    let _ = tx.send(Err(err)).await;
+                    return Err(std::io::Error::other(msg));
+                }
+            }
+        }
+        Ok(())
+    };
+    let body = Body::from_stream(ReceiverStream::new(rx));
+    let receive = async {
+        let mp = Multipart::from_request(Request::from_parts(parts, body), &()).await?;
+        controller::receive(scan.cfg, scan.ctx, scan.query, scan.client, scan.alerts, mp).await
+    };
+    tokio::pin!(tee, receive);
+    let mut teed = None;
+    let response = loop {
+        select! {
+            res = &mut tee, if teed.is_none() => teed = Some(res),
+            res = &mut receive => break res?,
+        }
+    };
+    if !response.is_clean() {
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response());
+    }
+    match teed {
+        Some(res) => res?,
+        None => tee.await?,
+    }
+    let size = raw.as_file().metadata()?.len();
+    let file = tokio::fs::File::open(raw.path()).await?;
+    let mut builder = http
+        .post(url)
+        .header(header::CONTENT_LENGTH, size)
+        .body(reqwest::Body::wrap_stream(ReaderStream::new(file)));
+    for (name, value) in headers.iter().filter(|(name, _)| is_forwarded(name)) {
+        builder = builder.header(name, value);
+    }
+    for (name, value) in verdict_headers(scan.ctx, &response).iter() {
+        builder = builder.header(name, value);
+    }
+    let upstream_resp = builder
+        .send()
+        .await
+        .map_err(|err| ErrorKind::Upstream(err.to_string()))?;
+    let mut resp = Response::builder().status(upstream_resp.status());
+    for (name, value) in upstream_resp.headers() {
+        if !is_hop_by_hop(name) {
+            resp = resp.header(name, value);
+        }
+    }
+    resp.body(Body::from_stream(upstream_resp.bytes_stream()))
+        .map_err(|err| ErrorKind::Upstream(err.to_string()).into())
+}
+
+/// Headers which only apply to a single connection, see RFC 9110 section 7.6.1.
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "connection"
+            | "keep-alive"
+            | "proxy-authenticate"
+            | "proxy-authorization"
+            | "proxy-connection"
+            | "te"
+            | "trailer"
+            | "transfer-encoding"
+            | "upgrade"
+    )
+}
+
+fn is_forwarded(name: &HeaderName) -> bool {
+    !is_hop_by_hop(name)
+        && *name != header::HOST
+        && *name != header::CONTENT_LENGTH
+        && !name.as_str().starts_with(SCAN_HEADER_PREFIX)
+}
+
+/// The distinct results of the files, e.g. `CLEAN` or `CLEAN,WHITELISTED`.
+fn summary(response: &AvResponse) -> String {
+    let mut results = Vec::new();
+    for result in response.results().iter().map(|r| r.result()) {
+        if !results.contains(&result) {
+            results.push(result);
+        }
+    }
+    match results.is_empty() {
+        true => "CLEAN".to_owned(),
+        false => results.join(","),
+    }
+}
+
+fn verdict_headers(ctx: &AvContext, response: &AvResponse) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = value.parse() {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    };
+    insert("x-scan-result", summary(response));
+    insert("x-scan-files", response.results().len().to_string());
+    insert("x-scan-av-version", ctx.clamav_version.to_owned());
+    insert("x-scan-db-version", ctx.db_version.to_string());
+    headers
+}
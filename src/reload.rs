@@ -0,0 +1,202 @@
+use axum::{Extension, Json, extract::Request, middleware::Next, response::Response};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::StatusCode;
+use serde::Serialize;
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    select,
+    signal::unix::{SignalKind, signal},
+};
+
+use crate::{
+    app_config::{self, AppConfig, ConfigError, Source, Sources},
+    cli::Overrides,
+    telemetry::{self, LogFilter},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Holds the active config, which is swapped as a whole on reload so that
+/// each request sees a consistent snapshot.
+pub struct ConfigHandle {
+    active: RwLock<Arc<Active>>,
+}
+
+struct Active {
+    config: Arc<AppConfig>,
+    sources: Sources,
+    loaded_at: DateTime<Utc>,
+    restart_required: Vec<&'static str>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: AppConfig, sources: Sources) -> Self {
+        Self {
+            active: RwLock::new(Arc::new(Active {
+                config: Arc::new(config),
+                sources,
+                loaded_at: Utc::now(),
+                restart_required: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn current(&self) -> Arc<AppConfig> {
+        Arc::clone(&self.active.read().unwrap().config)
+    }
+
+    fn active(&self) -> Arc<Active> {
+        Arc::clone(&self.active.read().unwrap())
+    }
+}
+
+/// Reloads the config on `SIGHUP` or when the config file is modified.
+pub struct Reloader {
+    pub handle: Arc<ConfigHandle>,
+    pub path: Option<PathBuf>,
+    pub overrides: Overrides,
+    pub log_filter: LogFilter,
+}
+
+impl Reloader {
+    pub async fn run(self) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                tracing::warn!("Failed to install SIGHUP handler: {}", err);
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut modified = self.modified();
+        loop {
+            select! {
+                _ = hangup.recv() => tracing::info!("Received SIGHUP, reloading config"),
+                _ = interval.tick() => {
+                    let current = self.modified();
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    tracing::info!("Config file changed, reloading config");
+                }
+            }
+            if let Err(err) = self.reload() {
+                tracing::error!("Keeping the active config: {}", err);
+            }
+        }
+    }
+
+    /// Loads and validates the config like on startup, then swaps in the
+    /// reloadable settings. Command line flags still take precedence.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let (mut cfg, mut sources) = app_config::load(self.path.as_deref())?;
+        for key in self.overrides.apply(&mut cfg) {
+            sources.set(key, Source::Flag(key.to_owned()));
+        }
+        cfg.validate()?;
+        let running = self.handle.current();
+        let restart_required = cfg.keep_restart_settings(&running);
+        for key in &restart_required {
+            tracing::warn!("Changed setting {} requires a restart", key);
+        }
+        if cfg.log_level != running.log_level {
+            let filter = telemetry::env_filter(cfg.log_level.as_deref());
+            if let Err(err) = self.log_filter.reload(filter) {
+                tracing::warn!("Failed to apply log_level: {}", err);
+            }
+        }
+        tracing::info!("Reloaded config\n{}", cfg);
+        *self.handle.active.write().unwrap() = Arc::new(Active {
+            config: Arc::new(cfg),
+            sources,
+            loaded_at: Utc::now(),
+            restart_required,
+        });
+        Ok(())
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        app_config::config_path(self.path.as_deref())
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|meta| meta.modified().ok())
+    }
+}
+
+/// Makes the current config snapshot available as `Extension<Arc<AppConfig>>`.
+pub async fn inject_config(
+    Extension(handle): Extension<Arc<ConfigHandle>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    req.extensions_mut().insert(handle.current());
+    next.run(req).await
+}
+
+#[derive(Serialize)]
+pub struct ActiveConfig {
+    config: serde_json::Value,
+    sources: serde_json::Value,
+    #[serde(rename = "loadedAt")]
+    loaded_at: String,
+    #[serde(rename = "restartRequired")]
+    restart_required: Vec<&'static str>,
+}
+
+/// Shows the active config with credentials redacted, where each
+/// value came from and which changed settings are pending a restart.
+pub async fn active_config(
+    Extension(handle): Extension<Arc<ConfigHandle>>,
+) -> Result<Json<ActiveConfig>, StatusCode> {
+    let active = handle.active();
+    if !active.config.enable_admin_endpoint {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut config = serde_json::to_value(active.config.as_ref())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    for key in ["otlp_endpoint", "alert_syslog", "proxy_upstream"] {
+        if let Some(redacted) = config[key].as_str().map(redact_url) {
+            config[key] = redacted.into();
+        }
+    }
+    if let Some(s3) = config.get_mut("s3").and_then(|s3| s3.as_object_mut()) {
+        if let Some(redacted) = s3["endpoint"].as_str().map(redact_url) {
+            s3["endpoint"] = redacted.into();
+        }
+        for key in ["secret_key", "session_token"] {
+            if s3.get(key).is_some_and(|v| !v.is_null()) {
+                s3[key] = "<redacted>".into();
+            }
+        }
+    }
+    Ok(Json(ActiveConfig {
+        config,
+        sources: serde_json::to_value(&active.sources)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        loaded_at: active
+            .loaded_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        restart_required: active.restart_required.clone(),
+    }))
+}
+
+/// Masks the user info and the query of a URL, which may carry credentials.
+fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_owned();
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, _)) => (rest, "?<redacted>"),
+        None => (rest, ""),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let authority = match authority.rsplit_once('@') {
+        Some((_, host)) => format!("<redacted>@{}", host),
+        None => authority.to_owned(),
+    };
+    format!("{}://{}{}{}", scheme, authority, path, query)
+}
@@ -0,0 +1,508 @@
+use axum::{
+    Extension, Json,
+    extract::{ConnectInfo, rejection::JsonRejection},
+    http::{HeaderMap, Method, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+use tokio_stream::StreamExt;
+use utoipa::ToSchema;
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    av::AvState,
+    controller::{self, AvResponse, AvResult, PartInfo, ScanRequest, Spool, UploadQuery},
+    error::{ApiError, ErrorKind, Problem},
+};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+/// Tags written by the service, replaced on every scan.
+const TAG_KEYS: &[&str] = &[
+    "scan-result",
+    "scan-signature",
+    "scan-date",
+    "scan-db-version",
+];
+
+/// S3-compatible object storage, e.g. AWS S3 or MinIO.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub region: String,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub session_token: Option<String>,
+    pub path_style: bool,
+    pub tag_results: bool,
+    /// Buckets which may be scanned, empty allows any bucket.
+    pub buckets: Vec<String>,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            region: "us-east-1".to_owned(),
+            access_key: None,
+            secret_key: None,
+            session_token: None,
+            path_style: true,
+            tag_results: false,
+            buckets: Vec::new(),
+        }
+    }
+}
+
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl S3Config {
+    /// Credentials from the config, falling back to the usual `AWS_*`
+    /// variables. Requests are sent unsigned without credentials.
+    fn credentials(&self) -> Option<Credentials> {
+        let env = |var| std::env::var(var).ok();
+        Some(Credentials {
+            access_key: self
+                .access_key
+                .clone()
+                .or_else(|| env("AWS_ACCESS_KEY_ID"))?,
+            secret_key: self
+                .secret_key
+                .clone()
+                .or_else(|| env("AWS_SECRET_ACCESS_KEY"))?,
+            session_token: self
+                .session_token
+                .clone()
+                .or_else(|| env("AWS_SESSION_TOKEN")),
+        })
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct S3Object {
+    bucket: String,
+    key: String,
+    #[serde(rename = "versionId")]
+    version_id: Option<String>,
+    /// Overrides `s3.tag_results` for this request.
+    tag: Option<bool>,
+}
+
+/// Streams an object from the configured S3 endpoint into the scan pipeline
+/// and responds like `/upload`. Optionally writes the verdict back as object
+/// tags, keeping any other tags. Responds with `404` if S3 is not configured.
+#[utoipa::path(
+    post,
+    path = "/scan/s3",
+    tag = "scan",
+    params(UploadQuery),
+    request_body = S3Object,
+    responses(
+        (status = 200, description = "The result of the object", body = AvResponse),
+        (status = "4XX", description = "Invalid request or limit exceeded", body = Problem, content_type = "application/problem+json"),
+        (status = "5XX", description = "Scan engine or upstream failure", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn scan_object(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    Extension(http): Extension<reqwest::Client>,
+    query: UploadQuery,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    headers: HeaderMap,
+    object: Result<Json<S3Object>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Some(s3) = &cfg.s3 else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let client = controller::audit_client(&headers, connect_info);
+    let Json(object) =
+        object.map_err(|err| ApiError::from(err).with_request_id(client.request_id.as_deref()))?;
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => {
+            let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+            let scan = ScanRequest {
+                cfg: &cfg,
+                ctx,
+                query: &query,
+                client: &client,
+                alerts,
+            };
+            let bucket = Bucket {
+                s3,
+                http: &http,
+                credentials: s3.credentials(),
+            };
+            fetch_and_scan(scan, &bucket, &object).await
+        }
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    result
+        .map(|response| Json(response).into_response())
+        .map_err(|err| err.with_request_id(client.request_id.as_deref()))
+}
+
+async fn fetch_and_scan(
+    scan: ScanRequest<'_>,
+    bucket: &Bucket<'_>,
+    object: &S3Object,
+) -> Result<AvResponse, ApiError> {
+    if object.bucket.is_empty() || object.key.is_empty() {
+        return Err(ErrorKind::InvalidRequest("bucket and key are required".to_owned()).into());
+    }
+    // The URL would resolve these segments, possibly to another bucket.
+    if object
+        .key
+        .split('/')
+        .any(|segment| segment == "." || segment == "..")
+    {
+        let err = "key must not contain '.' or '..' segments".to_owned();
+        return Err(ErrorKind::InvalidRequest(err).into());
+    }
+    let buckets = &bucket.s3.buckets;
+    if !buckets.is_empty() && !buckets.contains(&object.bucket) {
+        return Err(ErrorKind::BucketNotAllowed(object.bucket.clone()).into());
+    }
+    let options = scan.query.options(scan.cfg)?;
+    let max_file_size = scan.cfg.max_file_size;
+    let resp = bucket.send(Method::GET, object, false, Vec::new()).await?;
+    let resp = check_status(resp, object)?;
+    if resp
+        .content_length()
+        .is_some_and(|len| len > max_file_size as u64)
+    {
+        return Err(ErrorKind::FileTooLarge(max_file_size).into());
+    }
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+    let mut spool = Spool::new(&options.algorithms)?;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|err| ErrorKind::Upstream(err.to_string()))?;
+        if spool.size() + chunk.len() as u64 > max_file_size as u64 {
+            return Err(ErrorKind::FileTooLarge(max_file_size).into());
+        }
+        spool.write(&chunk)?;
+    }
+    let (tmp, size, hashes) = spool.finish()?;
+    let started = Instant::now();
+    let part = PartInfo {
+        name: None,
+        file_name: Some(&object.key),
+        content_type: content_type.as_deref(),
+    };
+    let result = controller::scan(scan.ctx, part, tmp.path(), size, hashes, &options).await?;
+    controller::report(
+        &result,
+        scan.ctx,
+        scan.client,
+        scan.alerts,
+        started.elapsed(),
+    )
+    .await;
+    let tagged = match object.tag.unwrap_or(bucket.s3.tag_results) {
+        true => tag(bucket, object, &result, scan.ctx.db_version).await,
+        false => Ok(()),
+    };
+    let response = AvResponse::new(scan.ctx, vec![result]);
+    match tagged {
+        Ok(()) => Ok(response),
+        Err(err) => {
+            tracing::warn!(
+                "Failed to tag s3://{}/{}: {}",
+                object.bucket,
+                object.key,
+                err
+            );
+            Ok(response.with_warning(format!("failed to write the tags: {}", err)))
+        }
+    }
+}
+
+/// Merges the verdict into the existing tags of the object.
+async fn tag(
+    bucket: &Bucket<'_>,
+    object: &S3Object,
+    result: &AvResult,
+    db_version: u32,
+) -> Result<(), ApiError> {
+    let resp = bucket.send(Method::GET, object, true, Vec::new()).await?;
+    let xml = check_status(resp, object)?
+        .text()
+        .await
+        .map_err(|err| ErrorKind::Upstream(err.to_string()))?;
+    let mut tags = parse_tags(&xml);
+    tags.retain(|(key, _)| !TAG_KEYS.contains(&key.as_str()));
+    tags.push(("scan-result".to_owned(), result.result().to_owned()));
+    if let Some(signature) = result.signature() {
+        tags.push(("scan-signature".to_owned(), signature.to_owned()));
+    }
+    tags.push(("scan-date".to_owned(), result.date_scanned().to_owned()));
+    tags.push(("scan-db-version".to_owned(), db_version.to_string()));
+    let resp = bucket
+        .send(Method::PUT, object, true, tagging_xml(&tags).into_bytes())
+        .await?;
+    check_status(resp, object)?;
+    Ok(())
+}
+
+fn check_status(resp: reqwest::Response, object: &S3Object) -> Result<reqwest::Response, ApiError> {
+    match resp.status() {
+        status if status.is_success() => Ok(resp),
+        StatusCode::NOT_FOUND => {
+            let url = format!("s3://{}/{}", object.bucket, object.key);
+            Err(ErrorKind::NotFound(url).into())
+        }
+        status => Err(ErrorKind::Upstream(format!("S3 responded with {}", status)).into()),
+    }
+}
+
+struct Bucket<'a> {
+    s3: &'a S3Config,
+    http: &'a reqwest::Client,
+    credentials: Option<Credentials>,
+}
+
+impl Bucket<'_> {
+    /// Sends a request for the object, or for its `?tagging` subresource,
+    /// signed with Signature Version 4 if credentials are configured.
+    async fn send(
+        &self,
+        method: Method,
+        object: &S3Object,
+        tagging: bool,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, ApiError> {
+        let invalid = |err: String| ErrorKind::Upstream(format!("s3.endpoint: {}", err));
+        let mut url =
+            reqwest::Url::parse(&self.s3.endpoint).map_err(|err| invalid(err.to_string()))?;
+        let base = url.path().trim_end_matches('/').to_owned();
+        let key = uri_encode(&object.key, false);
+        if self.s3.path_style {
+            url.set_path(&format!(
+                "{}/{}/{}",
+                base,
+                uri_encode(&object.bucket, true),
+                key
+            ));
+        } else {
+            let host = format!("{}.{}", object.bucket, url.host_str().unwrap_or_default());
+            url.set_host(Some(&host))
+                .map_err(|err| invalid(err.to_string()))?;
+            url.set_path(&format!("{}/{}", base, key));
+        }
+        let mut params = Vec::new();
+        if tagging {
+            params.push(("tagging", ""));
+        }
+        if let Some(version) = &object.version_id {
+            params.push(("versionId", version));
+        }
+        let query = canonical_query(&params);
+        url.set_query((!query.is_empty()).then_some(query.as_str()));
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(invalid("missing host".to_owned()).into()),
+        };
+
+        let now = Utc::now();
+        let payload_hash = const_hex::encode(Sha256::digest(&body));
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+        let credentials = self.credentials.as_ref();
+        if let Some(token) = credentials.and_then(|c| c.session_token.as_ref()) {
+            headers.push(("x-amz-security-token", token.to_owned()));
+        }
+        let mut builder = self.http.request(method.clone(), url.clone());
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            builder = builder.header(*name, value);
+        }
+        if let Some(credentials) = credentials {
+            let canonical = Canonical {
+                method: method.as_str(),
+                uri: url.path(),
+                query: &query,
+                headers: &headers,
+                payload_hash: &payload_hash,
+            };
+            let auth = authorization(credentials, &self.s3.region, &canonical, now);
+            builder = builder.header(header::AUTHORIZATION, auth);
+        }
+        if !body.is_empty() {
+            // S3 requires an integrity check for tagging requests.
+            builder = builder.header("content-md5", BASE64.encode(md5::Md5::digest(&body)));
+        }
+        builder
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| ErrorKind::Upstream(err.to_string()).into())
+    }
+}
+
+/// The parts of a request covered by the signature. `headers` must be
+/// lowercase and sorted by name.
+pub struct Canonical<'a> {
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub query: &'a str,
+    pub headers: &'a [(&'a str, String)],
+    pub payload_hash: &'a str,
+}
+
+/// Computes the `Authorization` header of AWS Signature Version 4.
+pub fn authorization(
+    credentials: &Credentials,
+    region: &str,
+    request: &Canonical,
+    time: DateTime<Utc>,
+) -> String {
+    let signed_headers = request
+        .headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect::<String>();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        request.uri,
+        request.query,
+        canonical_headers,
+        signed_headers,
+        request.payload_hash
+    );
+    let date = time.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        time.format("%Y%m%dT%H%M%SZ"),
+        scope,
+        const_hex::encode(Sha256::digest(canonical_request))
+    );
+    let secret = format!("AWS4{}", credentials.secret_key);
+    let mut key = hmac(secret.as_bytes(), date.as_bytes());
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    let signature = const_hex::encode(hmac(&key, string_to_sign.as_bytes()));
+    format!(
+        "{} Credential={}/{},SignedHeaders={},Signature={}",
+        ALGORITHM, credentials.access_key, scope, signed_headers, signature
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and, unless
+/// `encode_slash`, the path separator.
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn canonical_query(params: &[(&str, &str)]) -> String {
+    let mut pairs = params
+        .iter()
+        .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Extracts the key-value pairs of a `<Tagging>` document.
+fn parse_tags(xml: &str) -> Vec<(String, String)> {
+    xml.split("<Tag>")
+        .skip(1)
+        .filter_map(|tag| {
+            let tag = tag.split("</Tag>").next()?;
+            let key = xml_element(tag, "Key")?;
+            let value = xml_element(tag, "Value")?;
+            Some((xml_unescape(key), xml_unescape(value)))
+        })
+        .collect()
+}
+
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    if xml.contains(&format!("<{}/>", name)) {
+        return Some("");
+    }
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..end])
+}
+
+fn tagging_xml(tags: &[(String, String)]) -> String {
+    let tags = tags
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+                xml_escape(key),
+                xml_escape(value)
+            )
+        })
+        .collect::<String>();
+    format!("<Tagging><TagSet>{}</TagSet></Tagging>", tags)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
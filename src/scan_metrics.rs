@@ -0,0 +1,93 @@
+use chrono::Utc;
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use crate::av::AvState;
+
+/// Limits the cardinality of the signature label. Signatures detected after
+/// this many distinct ones have been seen are counted as `other`.
+const MAX_SIGNATURE_LABELS: usize = 100;
+
+static SIGNATURE_LABELS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+pub fn describe() {
+    metrics::describe_counter!("scans_total", "Scanned files by verdict");
+    metrics::describe_counter!("scan_signatures_total", "Detected signatures");
+    metrics::describe_counter!("scan_content_types_total", "Scanned files by content type");
+    metrics::describe_counter!(
+        "scan_bytes_total",
+        metrics::Unit::Bytes,
+        "Total size of scanned files"
+    );
+    metrics::describe_histogram!(
+        "scan_duration_seconds",
+        metrics::Unit::Seconds,
+        "Time spent in libclamav per file"
+    );
+    metrics::describe_histogram!(
+        "scan_tmp_write_duration_seconds",
+        metrics::Unit::Seconds,
+        "Time spent writing an upload to its temp file"
+    );
+    metrics::describe_gauge!("db_version", "Version of the loaded database");
+    metrics::describe_gauge!("db_sig_count", "Signature count of the loaded database");
+    metrics::describe_gauge!(
+        "db_age_seconds",
+        metrics::Unit::Seconds,
+        "Age of the loaded database"
+    );
+}
+
+pub fn record_write(duration: Duration) {
+    metrics::histogram!("scan_tmp_write_duration_seconds").record(duration);
+}
+
+pub fn record_scan(
+    result: &'static str,
+    signature: Option<&str>,
+    content_type: Option<&'static str>,
+    size: u64,
+    duration: Duration,
+) {
+    metrics::counter!("scans_total", "verdict" => result).increment(1);
+    if let Some(sig) = signature {
+        metrics::counter!("scan_signatures_total", "signature" => signature_label(sig))
+            .increment(1);
+    }
+    metrics::counter!("scan_content_types_total", "content_type" => content_type.unwrap_or("unknown"))
+        .increment(1);
+    metrics::counter!("scan_bytes_total").increment(size);
+    metrics::histogram!("scan_duration_seconds").record(duration);
+}
+
+/// Updates the database gauges, called right before rendering.
+pub fn record_engine(av: &AvState) {
+    if let Some(ctx) = av.get() {
+        metrics::gauge!("db_version").set(ctx.db_version);
+        metrics::gauge!("db_sig_count").set(ctx.db_sig_count);
+        metrics::gauge!("db_age_seconds").set((Utc::now() - ctx.db_date).num_seconds() as f64);
+    }
+}
+
+fn signature_label(signature: &str) -> String {
+    let label: String = signature
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "._-:".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    let mut labels = SIGNATURE_LABELS.lock().unwrap();
+    if labels.contains(&label) {
+        label
+    } else if labels.len() < MAX_SIGNATURE_LABELS {
+        labels.insert(label.clone());
+        label
+    } else {
+        "other".to_owned()
+    }
+}
@@ -0,0 +1,145 @@
+use axum::http::Request;
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    filter::{LevelFilter, Targets},
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+};
+
+use crate::{
+    app_config::{AppConfig, LogFormat},
+    audit,
+};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Swaps the filter of the local logs at runtime.
+pub type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+/// Builds the OTLP/HTTP span exporter pipeline if `otlp_endpoint` is set.
+pub fn tracer_provider(cfg: &AppConfig) -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    let Some(endpoint) = &cfg.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(cfg.otlp_service_name.to_owned())
+        .build();
+    Ok(Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build(),
+    ))
+}
+
+/// Filters local logs by `log_level` if set, by `RUST_LOG` otherwise. Audit
+/// events are always excluded.
+pub fn env_filter(log_level: Option<&str>) -> EnvFilter {
+    let filter = match log_level.map(EnvFilter::try_new) {
+        Some(Ok(filter)) => filter,
+        Some(Err(err)) => {
+            eprintln!("Invalid log_level, falling back to RUST_LOG: {}", err);
+            EnvFilter::from_default_env()
+        }
+        None => EnvFilter::from_default_env(),
+    };
+    filter.add_directive(
+        format!("{}=off", audit::TARGET)
+            .parse()
+            .expect("valid directive"),
+    )
+}
+
+/// Installs the global subscriber. Local logs are filtered by [`env_filter`],
+/// while spans are exported at `INFO` level regardless. Audit events only go
+/// to the audit log.
+pub fn init(cfg: &AppConfig) -> (Option<SdkTracerProvider>, LogFilter) {
+    let provider = tracer_provider(cfg).unwrap_or_else(|err| {
+        eprintln!("Failed to set up OTLP export: {}", err);
+        None
+    });
+    let (filter, log_filter) = reload::Layer::new(env_filter(cfg.log_level.as_deref()));
+    let mut layers: Vec<BoxedLayer> = vec![
+        log_layer(cfg.log_format, std::io::stdout)
+            .with_filter(filter)
+            .boxed(),
+    ];
+    if let Some(audit_log) = &cfg.audit_log {
+        match audit_layer(audit_log) {
+            Ok(layer) => layers.push(layer),
+            Err(err) => eprintln!("Failed to open audit log {}: {}", audit_log, err),
+        }
+    }
+    if let Some(p) = &provider {
+        let otel = tracing_opentelemetry::layer()
+            .with_tracer(p.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(LevelFilter::INFO);
+        layers.push(otel.boxed());
+    }
+    tracing_subscriber::registry().with(layers).init();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    (provider, log_filter)
+}
+
+/// Formats local logs as text or as JSON lines.
+pub fn log_layer<W>(format: LogFormat, writer: W) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Writes audit events as JSON lines to `stdout` or appends them to a file.
+pub fn audit_layer(audit_log: &str) -> Result<BoxedLayer, std::io::Error> {
+    let targets = Targets::new().with_target(audit::TARGET, LevelFilter::INFO);
+    let layer = tracing_subscriber::fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(false);
+    Ok(match audit_log {
+        "stdout" => layer
+            .with_writer(std::io::stdout)
+            .with_filter(targets)
+            .boxed(),
+        path => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            layer
+                .with_writer(std::sync::Mutex::new(file))
+                .with_filter(targets)
+                .boxed()
+        }
+    })
+}
+
+/// Creates the HTTP request span as a child of the W3C `traceparent` if any.
+pub fn make_span<B>(req: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+    );
+    let cx = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(req.headers())));
+    let _ = span.set_parent(cx);
+    span
+}
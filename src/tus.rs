@@ -0,0 +1,454 @@
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{ConnectInfo, Path},
+    http::{HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, TimeDelta, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    audit::Client,
+    av::{AvContext, AvState},
+    controller::{self, AvResponse, PartInfo, Spool, UploadQuery},
+    error::{ApiError, ErrorKind},
+    hashes::HashAlgorithm,
+};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TusConfig {
+    pub expiration_secs: u64,
+    /// Uploads kept at once, in progress or completed until they expire.
+    pub max_uploads: usize,
+}
+
+impl Default for TusConfig {
+    fn default() -> Self {
+        Self {
+            expiration_secs: 24 * 60 * 60,
+            max_uploads: 1000,
+        }
+    }
+}
+
+/// Uploads in progress, and the results of completed ones, until they expire.
+#[derive(Default)]
+pub struct TusStore {
+    uploads: Mutex<HashMap<String, Entry>>,
+}
+
+struct Entry {
+    expires: DateTime<Utc>,
+    upload: Arc<tokio::sync::Mutex<Upload>>,
+}
+
+struct Upload {
+    length: u64,
+    offset: u64,
+    query: UploadQuery,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    algorithms: Vec<HashAlgorithm>,
+    /// Opened by the first `PATCH` and taken once all bytes have been
+    /// received.
+    spool: Option<Spool>,
+    response: Option<AvResponse>,
+}
+
+type Shared = (DateTime<Utc>, Arc<tokio::sync::Mutex<Upload>>);
+
+impl TusStore {
+    /// Looks up an upload, dropping all expired ones along with their temp
+    /// files.
+    fn get(&self, id: &str) -> Result<Shared, ApiError> {
+        let mut uploads = self.uploads.lock().unwrap();
+        sweep(&mut uploads);
+        uploads
+            .get(id)
+            .map(|entry| (entry.expires, Arc::clone(&entry.upload)))
+            .ok_or_else(|| ErrorKind::NotFound(format!("upload {}", id)).into())
+    }
+
+    /// Adds an upload unless `max_uploads` are kept already, after dropping
+    /// the expired ones.
+    fn insert(
+        &self,
+        id: String,
+        expires: DateTime<Utc>,
+        upload: Upload,
+        max_uploads: usize,
+    ) -> Result<(), ApiError> {
+        let mut uploads = self.uploads.lock().unwrap();
+        sweep(&mut uploads);
+        if uploads.len() >= max_uploads {
+            return Err(ErrorKind::TooManyUploads(max_uploads).into());
+        }
+        let upload = Arc::new(tokio::sync::Mutex::new(upload));
+        uploads.insert(id, Entry { expires, upload });
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> bool {
+        self.uploads.lock().unwrap().remove(id).is_some()
+    }
+}
+
+fn sweep(uploads: &mut HashMap<String, Entry>) {
+    let now = Utc::now();
+    uploads.retain(|_, entry| entry.expires > now);
+}
+
+/// Announces the supported tus version and extensions.
+pub async fn options(Extension(cfg): Extension<Arc<AppConfig>>) -> Response {
+    if cfg.tus.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let mut resp = StatusCode::NO_CONTENT.into_response();
+    set(&mut resp, "tus-version", TUS_VERSION);
+    set(&mut resp, "tus-extension", TUS_EXTENSIONS);
+    set(&mut resp, "tus-max-size", cfg.max_file_size);
+    respond(Ok(resp))
+}
+
+/// Creates an upload of `Upload-Length` bytes. The query parameters of
+/// `/upload` are validated right away and applied once it completes.
+pub async fn create(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(store): Extension<Arc<TusStore>>,
+    query: UploadQuery,
+    headers: HeaderMap,
+) -> Response {
+    let Some(tus) = &cfg.tus else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(resp) = check_version(&headers) {
+        return resp;
+    }
+    respond(create_upload(&cfg, tus, &store, query, &headers))
+}
+
+fn create_upload(
+    cfg: &AppConfig,
+    tus: &TusConfig,
+    store: &TusStore,
+    query: UploadQuery,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let length = header_u64(headers, "upload-length").ok_or_else(|| {
+        ErrorKind::InvalidRequest(
+            "Upload-Length is required, deferred lengths are not supported".to_owned(),
+        )
+    })?;
+    if length == 0 {
+        return Err(
+            ErrorKind::InvalidRequest("Upload-Length must be greater than 0".to_owned()).into(),
+        );
+    }
+    if length > cfg.max_file_size as u64 {
+        return Err(ErrorKind::FileTooLarge(cfg.max_file_size).into());
+    }
+    let options = query.options(cfg)?;
+    let mut metadata = parse_metadata(headers.get("upload-metadata"))?;
+    let upload = Upload {
+        length,
+        offset: 0,
+        file_name: metadata
+            .remove("filename")
+            .or_else(|| metadata.remove("name")),
+        content_type: metadata
+            .remove("filetype")
+            .or_else(|| metadata.remove("type")),
+        algorithms: options.algorithms,
+        spool: None,
+        query,
+        response: None,
+    };
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let expires = Utc::now() + TimeDelta::seconds(tus.expiration_secs as i64);
+    store.insert(id.clone(), expires, upload, tus.max_uploads)?;
+    let mut resp = StatusCode::CREATED.into_response();
+    set(&mut resp, "location", format!("/tus/{}", id));
+    set(&mut resp, "upload-expires", http_date(expires));
+    Ok(resp)
+}
+
+/// Reports the offset to resume from.
+pub async fn head(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(store): Extension<Arc<TusStore>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if cfg.tus.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(resp) = check_version(&headers) {
+        return resp;
+    }
+    let result = match store.get(&id) {
+        Ok((expires, upload)) => {
+            let upload = upload.lock().await;
+            let mut resp = StatusCode::OK.into_response();
+            set(&mut resp, "upload-offset", upload.offset);
+            set(&mut resp, "upload-length", upload.length);
+            set(&mut resp, "upload-expires", http_date(expires));
+            set(&mut resp, "cache-control", "no-store");
+            Ok(resp)
+        }
+        Err(err) => Err(err),
+    };
+    respond(result)
+}
+
+/// Appends the body at `Upload-Offset`. Once all bytes have been received,
+/// the file is scanned and the result is available from [`status`]. Bytes
+/// received before a connection drops are kept, so the client can resume.
+#[allow(clippy::too_many_arguments)]
+pub async fn patch(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(av): Extension<Arc<AvState>>,
+    Extension(store): Extension<Arc<TusStore>>,
+    Path(id): Path<String>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    alerts: Option<Extension<Arc<AlertSink>>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if cfg.tus.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(resp) = check_version(&headers) {
+        return resp;
+    }
+    let content_type = headers.get(header::CONTENT_TYPE);
+    if content_type.is_none_or(|v| v != OFFSET_OCTET_STREAM) {
+        return respond(Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response()));
+    }
+    let client = controller::audit_client(&headers, connect_info);
+    let _pending = av.track();
+    let result = match av.get() {
+        Some(ctx) => match receive(&store, &id, &headers, body).await {
+            Ok((expires, upload)) => {
+                let alerts = alerts.as_ref().map(|Extension(sink)| sink.as_ref());
+                let mut upload = upload.lock().await;
+                match complete(&cfg, ctx, &client, alerts, &mut upload).await {
+                    Ok(()) => {
+                        let mut resp = StatusCode::NO_CONTENT.into_response();
+                        set(&mut resp, "upload-offset", upload.offset);
+                        set(&mut resp, "upload-expires", http_date(expires));
+                        if let Some(result) =
+                            upload.response.as_ref().and_then(|r| r.results().first())
+                        {
+                            set(&mut resp, "x-scan-result", result.result());
+                        }
+                        Ok(resp)
+                    }
+                    Err(err) => {
+                        store.remove(&id);
+                        Err(err)
+                    }
+                }
+            }
+            Err(err) => Err(err),
+        },
+        None => Err(ErrorKind::EngineUnavailable(av.last_error()).into()),
+    };
+    respond(result.map_err(|err| err.with_request_id(client.request_id.as_deref())))
+}
+
+/// Writes the body to the spool, advancing the offset with every chunk.
+async fn receive(
+    store: &TusStore,
+    id: &str,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<Shared, ApiError> {
+    let offset = header_u64(headers, "upload-offset")
+        .ok_or_else(|| ErrorKind::InvalidRequest("Upload-Offset is required".to_owned()))?;
+    let (expires, shared) = store.get(id)?;
+    let mut guard = shared.lock().await;
+    let upload = &mut *guard;
+    // A completed upload accepts no more bytes at any offset.
+    if upload.offset != offset || upload.offset == upload.length {
+        return Err(ErrorKind::OffsetMismatch(upload.offset).into());
+    }
+    let spool = match &mut upload.spool {
+        Some(spool) => spool,
+        spool => spool.insert(Spool::new(&upload.algorithms)?),
+    };
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                tracing::info!("Upload {} interrupted at {}: {}", id, upload.offset, err);
+                break;
+            }
+        };
+        if upload.offset + chunk.len() as u64 > upload.length {
+            let err = format!("body exceeds Upload-Length of {} bytes", upload.length);
+            return Err(ErrorKind::InvalidRequest(err).into());
+        }
+        spool.write(&chunk)?;
+        upload.offset += chunk.len() as u64;
+    }
+    drop(guard);
+    Ok((expires, shared))
+}
+
+/// Scans the file and reports the result once all bytes have been received.
+async fn complete(
+    cfg: &AppConfig,
+    ctx: &AvContext,
+    client: &Client,
+    alerts: Option<&AlertSink>,
+    upload: &mut Upload,
+) -> Result<(), ApiError> {
+    if upload.offset < upload.length {
+        return Ok(());
+    }
+    let Some(spool) = upload.spool.take() else {
+        return Ok(());
+    };
+    let options = upload.query.options(cfg)?;
+    let (tmp, size, hashes) = spool.finish()?;
+    let started = Instant::now();
+    let part = PartInfo {
+        name: None,
+        file_name: upload.file_name.as_deref(),
+        content_type: upload.content_type.as_deref(),
+    };
+    let result = controller::scan(ctx, part, tmp.path(), size, hashes, &options).await?;
+    controller::report(&result, ctx, client, alerts, started.elapsed()).await;
+    upload.response = Some(AvResponse::new(ctx, vec![result]));
+    Ok(())
+}
+
+/// The status resource of an upload: the `AvResponse` once it is complete,
+/// otherwise `202 Accepted` with the current offset.
+#[utoipa::path(
+    get,
+    path = "/tus/{id}",
+    tag = "tus",
+    params(("id" = String, Path, description = "Upload ID from the `Location` of the creation")),
+    responses(
+        (status = 200, description = "The result of the completed upload", body = AvResponse),
+        (status = 202, description = "Still incomplete, see `Upload-Offset`"),
+        (status = 404, description = "Unknown or expired upload, or `[tus]` is not configured"),
+    )
+)]
+pub async fn status(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(store): Extension<Arc<TusStore>>,
+    Path(id): Path<String>,
+) -> Response {
+    if cfg.tus.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let result = match store.get(&id) {
+        Ok((_, upload)) => {
+            let upload = upload.lock().await;
+            match &upload.response {
+                Some(response) => Ok(Json(response).into_response()),
+                None => {
+                    let mut resp = StatusCode::ACCEPTED.into_response();
+                    set(&mut resp, "upload-offset", upload.offset);
+                    set(&mut resp, "upload-length", upload.length);
+                    Ok(resp)
+                }
+            }
+        }
+        Err(err) => Err(err),
+    };
+    respond(result)
+}
+
+/// Discards an upload and its result.
+pub async fn terminate(
+    Extension(cfg): Extension<Arc<AppConfig>>,
+    Extension(store): Extension<Arc<TusStore>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if cfg.tus.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(resp) = check_version(&headers) {
+        return resp;
+    }
+    match store.remove(&id) {
+        true => respond(Ok(StatusCode::NO_CONTENT.into_response())),
+        false => respond(Err(ErrorKind::NotFound(format!("upload {}", id)).into())),
+    }
+}
+
+/// Rejects requests for other protocol versions with `412`.
+fn check_version(headers: &HeaderMap) -> Option<Response> {
+    if headers
+        .get("tus-resumable")
+        .is_some_and(|v| v == TUS_VERSION)
+    {
+        return None;
+    }
+    let mut resp = StatusCode::PRECONDITION_FAILED.into_response();
+    set(&mut resp, "tus-version", TUS_VERSION);
+    Some(respond(Ok(resp)))
+}
+
+fn respond(result: Result<Response, ApiError>) -> Response {
+    let mut resp = result.into_response();
+    set(&mut resp, "tus-resumable", TUS_VERSION);
+    resp
+}
+
+fn set(resp: &mut Response, name: &'static str, value: impl ToString) {
+    if let Ok(value) = HeaderValue::try_from(value.to_string()) {
+        resp.headers_mut().insert(name, value);
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Parses comma separated pairs of a key and a base64 encoded value.
+fn parse_metadata(value: Option<&HeaderValue>) -> Result<HashMap<String, String>, ApiError> {
+    let mut metadata = HashMap::new();
+    let Some(value) = value else {
+        return Ok(metadata);
+    };
+    let invalid = || {
+        ApiError::from(ErrorKind::InvalidRequest(
+            "invalid Upload-Metadata".to_owned(),
+        ))
+    };
+    let value = value.to_str().map_err(|_| invalid())?;
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+        let decoded = BASE64.decode(encoded.trim()).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        metadata.insert(key.to_owned(), decoded);
+    }
+    Ok(metadata)
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
@@ -0,0 +1,236 @@
+use chrono::Utc;
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{select, sync::mpsc};
+
+use crate::{
+    alert::AlertSink,
+    app_config::AppConfig,
+    audit::Client,
+    av::{AvContext, AvState},
+    controller::{self, ScanOptions},
+    reload::ConfigHandle,
+};
+
+pub const CLEAN_DIR: &str = "clean";
+pub const INFECTED_DIR: &str = "infected";
+pub const ERROR_DIR: &str = "error";
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    pub dirs: Vec<PathBuf>,
+    pub stable_secs: u64,
+    pub rescan_secs: u64,
+    pub policy: Option<String>,
+    pub members: bool,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            dirs: Vec::new(),
+            stable_secs: 5,
+            rescan_secs: 30,
+            policy: None,
+            members: false,
+        }
+    }
+}
+
+/// Size and modification time last seen for a file, and since when.
+struct Pending {
+    state: Option<(u64, SystemTime)>,
+    since: Instant,
+}
+
+/// Scans new files dropped into the configured directories, then moves them
+/// to the `clean`, `infected` or `error` subdirectory along with a JSON
+/// sidecar holding the result.
+///
+/// inotify does not report changes made by other NFS clients, so the
+/// directories are also listed every `rescan_secs`. A file is only scanned
+/// once its size and modification time have not changed for `stable_secs`.
+/// Detections are sent to `alerts` like those of uploads.
+pub async fn run(
+    config: Arc<ConfigHandle>,
+    av: Arc<AvState>,
+    alerts: Option<Arc<AlertSink>>,
+) -> std::io::Result<()> {
+    let watch = config.current().watch.clone();
+    for dir in &watch.dirs {
+        for subdir in [CLEAN_DIR, INFECTED_DIR, ERROR_DIR] {
+            std::fs::create_dir_all(dir.join(subdir))?;
+        }
+    }
+    let (tx, mut rx) = mpsc::channel(1024);
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.blocking_send(res);
+    })
+    .map_err(std::io::Error::other)?;
+    for dir in &watch.dirs {
+        if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("Failed to watch {}, polling only: {}", dir.display(), err);
+        }
+    }
+    tracing::info!("Watching {:?}", watch.dirs);
+    let stable = Duration::from_secs(watch.stable_secs);
+    let mut pending = HashMap::new();
+    let mut tick = tokio::time::interval(TICK);
+    let mut rescan = tokio::time::interval(Duration::from_secs(watch.rescan_secs.max(1)));
+    loop {
+        select! {
+            Some(event) = rx.recv() => match event {
+                Ok(event) => {
+                    for path in event.paths {
+                        if is_candidate(&path, &watch.dirs) {
+                            pending.entry(path).or_insert_with(Pending::new);
+                        }
+                    }
+                }
+                Err(err) => tracing::warn!("Watch error: {}", err),
+            },
+            _ = rescan.tick() => {
+                for dir in &watch.dirs {
+                    match list(dir, &watch.dirs) {
+                        Ok(paths) => {
+                            for path in paths {
+                                pending.entry(path).or_insert_with(Pending::new);
+                            }
+                        }
+                        Err(err) => tracing::warn!("Failed to list {}: {}", dir.display(), err),
+                    }
+                }
+            }
+            _ = tick.tick() => {
+                let Some(ctx) = av.get() else {
+                    continue;
+                };
+                for path in stable_files(&mut pending, stable) {
+                    process(&config.current(), ctx, alerts.as_deref(), &path).await;
+                }
+            }
+        }
+    }
+}
+
+impl Pending {
+    fn new() -> Self {
+        Self {
+            state: None,
+            since: Instant::now(),
+        }
+    }
+}
+
+/// Files directly inside one of the watched directories, except hidden ones
+/// which are commonly used for partial uploads.
+fn is_candidate(path: &Path, dirs: &[PathBuf]) -> bool {
+    path.parent()
+        .is_some_and(|parent| dirs.iter().any(|d| d == parent))
+        && path
+            .file_name()
+            .is_some_and(|name| !name.to_string_lossy().starts_with('.'))
+        && path.is_file()
+}
+
+fn list(dir: &Path, dirs: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_candidate(&path, dirs) {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Removes and returns the files which have not changed for `stable`.
+fn stable_files(pending: &mut HashMap<PathBuf, Pending>, stable: Duration) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+    pending.retain(|path, p| {
+        let Ok(meta) = std::fs::metadata(path) else {
+            return false;
+        };
+        let state = meta.modified().ok().map(|modified| (meta.len(), modified));
+        if state.is_none() || state != p.state {
+            p.state = state;
+            p.since = Instant::now();
+            true
+        } else if p.since.elapsed() >= stable {
+            ready.push(path.to_owned());
+            false
+        } else {
+            true
+        }
+    });
+    ready.sort();
+    ready
+}
+
+async fn process(cfg: &AppConfig, ctx: &AvContext, alerts: Option<&AlertSink>, path: &Path) {
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let options = ScanOptions {
+        policy: cfg
+            .watch
+            .policy
+            .as_ref()
+            .and_then(|name| cfg.policies.get(name)),
+        algorithms: cfg.hashes.clone(),
+        members: cfg.watch.members,
+    };
+    let started = Instant::now();
+    let result = controller::scan_file(ctx, path, &name, &options).await;
+    let (subdir, sidecar) = match result {
+        Ok(result) => {
+            let client = Client::default();
+            controller::report(&result, ctx, &client, alerts, started.elapsed()).await;
+            let subdir = match result.result() {
+                "CLEAN" | "WHITELISTED" => CLEAN_DIR,
+                _ => INFECTED_DIR,
+            };
+            (subdir, serde_json::to_vec_pretty(&result))
+        }
+        Err(err) => {
+            tracing::error!("Failed to scan {}: {}", path.display(), err);
+            let error = serde_json::json!({ "name": name, "error": err.to_string() });
+            (ERROR_DIR, serde_json::to_vec_pretty(&error))
+        }
+    };
+    let delivered = sidecar
+        .map_err(std::io::Error::other)
+        .and_then(|sidecar| deliver(path, &dir.join(subdir), &name, &sidecar));
+    match delivered {
+        Ok(target) => tracing::info!("Moved {} to {}", path.display(), target.display()),
+        Err(err) => tracing::error!("Failed to move {}: {}", path.display(), err),
+    }
+}
+
+/// Writes the sidecar `<name>.json` first and then moves the file, so that
+/// the result is complete once the file shows up. An existing file of the
+/// same name is kept by prefixing the new one with a timestamp.
+fn deliver(path: &Path, dest: &Path, name: &str, sidecar: &[u8]) -> std::io::Result<PathBuf> {
+    let name = match dest.join(name).exists() {
+        true => format!("{}.{}", Utc::now().format("%Y%m%dT%H%M%S%.3f"), name),
+        false => name.to_owned(),
+    };
+    let tmp = dest.join(format!(".{}.json.tmp", name));
+    std::fs::write(&tmp, sidecar)?;
+    std::fs::rename(&tmp, dest.join(format!("{}.json", name)))?;
+    let target = dest.join(name);
+    std::fs::rename(path, &target)?;
+    Ok(target)
+}